@@ -21,14 +21,59 @@ Register-ArgumentCompleter -Native -CommandName 'sd' -ScriptBlock {
 
     $completions = @(switch ($command) {
         'sd' {
+            [CompletionResult]::new('-C', 'C ', [CompletionResultType]::ParameterName, 'Show NUM lines around each changed line in `--preview`. Only works on files, since STDIN gets previewed whole')
+            [CompletionResult]::new('--context', 'context', [CompletionResultType]::ParameterName, 'Show NUM lines around each changed line in `--preview`. Only works on files, since STDIN gets previewed whole')
+            [CompletionResult]::new('-A', 'A ', [CompletionResultType]::ParameterName, 'Show NUM lines after each changed line in `--preview`. Overrides `--context`')
+            [CompletionResult]::new('--after-context', 'after-context', [CompletionResultType]::ParameterName, 'Show NUM lines after each changed line in `--preview`. Overrides `--context`')
+            [CompletionResult]::new('-B', 'B ', [CompletionResultType]::ParameterName, 'Show NUM lines before each changed line in `--preview`. Overrides `--context`')
+            [CompletionResult]::new('--before-context', 'before-context', [CompletionResultType]::ParameterName, 'Show NUM lines before each changed line in `--preview`. Overrides `--context`')
+            [CompletionResult]::new('-U', 'U ', [CompletionResultType]::ParameterName, 'Show N lines of context around each change in `--diff`')
+            [CompletionResult]::new('--unified', 'unified', [CompletionResultType]::ParameterName, 'Show N lines of context around each change in `--diff`')
             [CompletionResult]::new('-n', 'n', [CompletionResultType]::ParameterName, 'Limit the number of replacements that can occur per file. 0 indicates unlimited replacements')
             [CompletionResult]::new('--max-replacements', 'max-replacements', [CompletionResultType]::ParameterName, 'Limit the number of replacements that can occur per file. 0 indicates unlimited replacements')
+            [CompletionResult]::new('--skip', 'skip', [CompletionResultType]::ParameterName, 'Leave the first K matches of each pattern alone')
+            [CompletionResult]::new('--every', 'every', [CompletionResultType]::ParameterName, 'Replace only every Nth match of each pattern, starting with the first one that isn''t skipped by `--skip`')
+            [CompletionResult]::new('--nth', 'nth', [CompletionResultType]::ParameterName, 'Replace only the Nth match of each pattern')
             [CompletionResult]::new('-f', 'f', [CompletionResultType]::ParameterName, 'Regex flags. May be combined (like `-f mc`).')
             [CompletionResult]::new('--flags', 'flags', [CompletionResultType]::ParameterName, 'Regex flags. May be combined (like `-f mc`).')
-            [CompletionResult]::new('-p', 'p', [CompletionResultType]::ParameterName, 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future)')
-            [CompletionResult]::new('--preview', 'preview', [CompletionResultType]::ParameterName, 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future)')
+            [CompletionResult]::new('-e', 'e', [CompletionResultType]::ParameterName, 'Add a find & replace pair. May be repeated to apply several replacements in order in a single pass over the input. When used, all positional arguments are treated as file paths')
+            [CompletionResult]::new('--expression', 'expression', [CompletionResultType]::ParameterName, 'Add a find & replace pair. May be repeated to apply several replacements in order in a single pass over the input. When used, all positional arguments are treated as file paths')
+            [CompletionResult]::new('--rules', 'rules', [CompletionResultType]::ParameterName, 'Read find & replace rules from a file, one rule per line. Each rule is written like sd''s own arguments (`[-F] [-f FLAGS] [-n LIMIT] FIND REPLACE_WITH`) with shell-like quoting, and lines starting with `#` are comments. May be repeated. Rules from files are applied after any `-e` pairs. When used, all positional arguments are treated as file paths')
+            [CompletionResult]::new('--max-match-length', 'max-match-length', [CompletionResultType]::ParameterName, 'Replace files bigger than 8 MiB a piece at a time with a bounded amount of memory by assuming that no match is longer than BYTES. This already happens when none of the patterns can match a line break. Longer matches spanning two pieces can be missed, which gets warned about whenever a longer match turns up')
+            [CompletionResult]::new('--lines', 'lines', [CompletionResultType]::ParameterName, 'Only replace within lines START through END, counting from 1. Either side can be left out (`10,` or `,20`), and a single number selects just that line')
+            [CompletionResult]::new('--from', 'from', [CompletionResultType]::ParameterName, 'Only replace within blocks of lines that start with a line matching the regex PATTERN, going up to the line matching `--to`. Without `--to`, the block goes on until the end. Can be combined with `--lines`')
+            [CompletionResult]::new('--to', 'to', [CompletionResultType]::ParameterName, 'Only replace within blocks of lines that end with a line matching the regex PATTERN. Without `--from`, the block starts at the first line')
+            [CompletionResult]::new('--backup', 'backup', [CompletionResultType]::ParameterName, 'Keep the original of each modified file next to it, with SUFFIX (`~` by default) added to its name. When that name is already taken, a number gets added after the suffix, as in `file~.1`. Files that end in SUFFIX are left out when searching directories')
+            [CompletionResult]::new('-g', 'g', [CompletionResultType]::ParameterName, 'Only replace in files matching GLOB, or leave out the ones matching it when it starts with `!`. May be repeated. Globs are matched against paths relative to the directory being searched, or against the path as given for files passed directly')
+            [CompletionResult]::new('--glob', 'glob', [CompletionResultType]::ParameterName, 'Only replace in files matching GLOB, or leave out the ones matching it when it starts with `!`. May be repeated. Globs are matched against paths relative to the directory being searched, or against the path as given for files passed directly')
+            [CompletionResult]::new('-t', 't', [CompletionResultType]::ParameterName, 'Only replace in files of type TYPE, like `rust` or `js`. May be repeated. See `--type-list` for all of the types')
+            [CompletionResult]::new('--type', 'type', [CompletionResultType]::ParameterName, 'Only replace in files of type TYPE, like `rust` or `js`. May be repeated. See `--type-list` for all of the types')
+            [CompletionResult]::new('-T', 'T ', [CompletionResultType]::ParameterName, 'Leave out files of type TYPE. May be repeated')
+            [CompletionResult]::new('--type-not', 'type-not', [CompletionResultType]::ParameterName, 'Leave out files of type TYPE. May be repeated')
+            [CompletionResult]::new('--type-add', 'type-add', [CompletionResultType]::ParameterName, 'Add a file type or add a glob to an existing one, as in `--type-add ''web:*.{html,css}''`. May be repeated')
+            [CompletionResult]::new('--encoding', 'encoding', [CompletionResultType]::ParameterName, 'Read and write text that doesn''t start with a byte order mark as ENCODING, like `utf-16le` or `latin1`, rather than as UTF-8. Text that does start with one is always transcoded accordingly, keeping the byte order mark')
+            [CompletionResult]::new('-p', 'p', [CompletionResultType]::ParameterName, 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future). For files, only the changed lines are shown, prefixed with their line numbers')
+            [CompletionResult]::new('--preview', 'preview', [CompletionResultType]::ParameterName, 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future). For files, only the changed lines are shown, prefixed with their line numbers')
+            [CompletionResult]::new('--check', 'check', [CompletionResultType]::ParameterName, 'Don''t change anything, only list the files that would change. Exits with a status of 2 if anything would change, including STDIN')
+            [CompletionResult]::new('--diff', 'diff', [CompletionResultType]::ParameterName, 'Don''t change anything, only print a unified diff of the changes that would be made')
             [CompletionResult]::new('-F', 'F ', [CompletionResultType]::ParameterName, 'Treat FIND and REPLACE_WITH args as literal strings')
             [CompletionResult]::new('--fixed-strings', 'fixed-strings', [CompletionResultType]::ParameterName, 'Treat FIND and REPLACE_WITH args as literal strings')
+            [CompletionResult]::new('--per-line', 'per-line', [CompletionResultType]::ParameterName, 'Count the matches for `--skip`, `--every` and `--nth` separately on each line instead of across the whole input')
+            [CompletionResult]::new('--simultaneous', 'simultaneous', [CompletionResultType]::ParameterName, 'Apply all find & replace pairs together in a single left-to-right pass instead of one after another. Text produced by one replacement is never matched by another, so `-e foo bar -e bar foo` swaps the two words. When several patterns match at the same position, the one that was given first wins')
+            [CompletionResult]::new('--line-by-line', 'line-by-line', [CompletionResultType]::ParameterName, 'Replace STDIN one line at a time, writing out each line as soon as it''s done. This already happens when none of the patterns can match a line break, and forcing it means that matches can''t span lines')
+            [CompletionResult]::new('--preserve-timestamps', 'preserve-timestamps', [CompletionResultType]::ParameterName, 'Keep the access and modification times of modified files as they were. Owners, groups, permissions, extended attributes and hard links are always kept where possible')
+            [CompletionResult]::new('--json', 'json', [CompletionResultType]::ParameterName, 'Print a JSON record for each match, with its path, byte offsets, line and column, matched text, capture groups and replacement, followed by a record for each file and a summary. One record is printed per line. Files still get replaced in unless `--preview` is passed as well. Offsets are into the text after it''s decoded to UTF-8, and all of the patterns are matched in a single pass, like with `--simultaneous`. Replacing in files with more than one pattern therefore needs `--simultaneous` too')
+            [CompletionResult]::new('--interactive', 'interactive', [CompletionResultType]::ParameterName, 'Ask about each match before replacing it, showing the lines around it along with how it would change. Answer with y (yes), n (no), a (this and all of the rest), q (none of the rest) or e (type in what to replace it with instead). Answers are read from the terminal, or from STDIN when there''s no terminal, and this only works on files. All of the patterns are matched in a single pass, so more than one pattern needs `--simultaneous`. Nothing gets changed until all of the questions are answered')
+            [CompletionResult]::new('--atomic', 'atomic', [CompletionResultType]::ParameterName, 'Replace in either all of the files or none of them. Every file gets replaced into a temporary file first, and only once all of them are ready do they take the place of the originals. If that fails for any of them, the ones already replaced are put back the way they were')
+            [CompletionResult]::new('--journal', 'journal', [CompletionResultType]::ParameterName, 'Keep the originals of modified files in a journal, so that the run can be undone with `--undo`. Only the last run''s journal is kept. It lives in the user''s state directory, or in $SD_JOURNAL_DIR if set')
+            [CompletionResult]::new('--undo', 'undo', [CompletionResultType]::ParameterName, 'Undo the last run that was made with `--journal`, putting back the originals of the files it modified. Nothing gets undone if any of the files were modified since')
+            [CompletionResult]::new('--type-list', 'type-list', [CompletionResultType]::ParameterName, 'Print all of the file types along with their globs, then exit')
+            [CompletionResult]::new('--binary', 'binary', [CompletionResultType]::ParameterName, 'Replace in files that look binary too. By default, files found by searching directories are skipped with a warning when the start of the file has a NUL character. Files passed by name are never skipped')
+            [CompletionResult]::new('--no-crlf', 'no-crlf', [CompletionResultType]::ParameterName, 'Don''t treat CRLF line breaks any differently. By default, when an input''s first line break is a CRLF, `^` and `$` match on either side of the whole CRLF and line breaks in the replacement become CRLFs too. The same goes for the patterns of `--from` and `--to`. Pass this to strip the `\r`s with `sd --no-crlf ''\r$'' ''''`')
+            [CompletionResult]::new('-o', 'o', [CompletionResultType]::ParameterName, 'Print only the replacement for each match, one per line, instead of the whole input. Files are left untouched. REPLACE_WITH defaults to the match itself (`$0`) when it''s left out. Empty matches are skipped. All of the patterns are matched in a single pass, like with `--simultaneous`')
+            [CompletionResult]::new('--only-matching', 'only-matching', [CompletionResultType]::ParameterName, 'Print only the replacement for each match, one per line, instead of the whole input. Files are left untouched. REPLACE_WITH defaults to the match itself (`$0`) when it''s left out. Empty matches are skipped. All of the patterns are matched in a single pass, like with `--simultaneous`')
+            [CompletionResult]::new('--with-filename', 'with-filename', [CompletionResultType]::ParameterName, 'Prefix each match printed by `--only-matching` with its file path')
+            [CompletionResult]::new('--line-number', 'line-number', [CompletionResultType]::ParameterName, 'Prefix each match printed by `--only-matching` with its line number')
             [CompletionResult]::new('-h', 'h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('--help', 'help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('-V', 'V ', [CompletionResultType]::ParameterName, 'Print version')
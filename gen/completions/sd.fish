@@ -1,6 +1,42 @@
+complete -c sd -s C -l context -d 'Show NUM lines around each changed line in `--preview`. Only works on files, since STDIN gets previewed whole' -r
+complete -c sd -s A -l after-context -d 'Show NUM lines after each changed line in `--preview`. Overrides `--context`' -r
+complete -c sd -s B -l before-context -d 'Show NUM lines before each changed line in `--preview`. Overrides `--context`' -r
+complete -c sd -s U -l unified -d 'Show N lines of context around each change in `--diff`' -r
 complete -c sd -s n -l max-replacements -d 'Limit the number of replacements that can occur per file. 0 indicates unlimited replacements' -r
+complete -c sd -l skip -d 'Leave the first K matches of each pattern alone' -r
+complete -c sd -l every -d 'Replace only every Nth match of each pattern, starting with the first one that isn\'t skipped by `--skip`' -r
+complete -c sd -l nth -d 'Replace only the Nth match of each pattern' -r
 complete -c sd -s f -l flags -d 'Regex flags. May be combined (like `-f mc`).' -r
-complete -c sd -s p -l preview -d 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future)'
+complete -c sd -s e -l expression -d 'Add a find & replace pair. May be repeated to apply several replacements in order in a single pass over the input. When used, all positional arguments are treated as file paths' -r
+complete -c sd -l rules -d 'Read find & replace rules from a file, one rule per line. Each rule is written like sd\'s own arguments (`[-F] [-f FLAGS] [-n LIMIT] FIND REPLACE_WITH`) with shell-like quoting, and lines starting with `#` are comments. May be repeated. Rules from files are applied after any `-e` pairs. When used, all positional arguments are treated as file paths' -r -F
+complete -c sd -l max-match-length -d 'Replace files bigger than 8 MiB a piece at a time with a bounded amount of memory by assuming that no match is longer than BYTES. This already happens when none of the patterns can match a line break. Longer matches spanning two pieces can be missed, which gets warned about whenever a longer match turns up' -r
+complete -c sd -l lines -d 'Only replace within lines START through END, counting from 1. Either side can be left out (`10,` or `,20`), and a single number selects just that line' -r
+complete -c sd -l from -d 'Only replace within blocks of lines that start with a line matching the regex PATTERN, going up to the line matching `--to`. Without `--to`, the block goes on until the end. Can be combined with `--lines`' -r
+complete -c sd -l to -d 'Only replace within blocks of lines that end with a line matching the regex PATTERN. Without `--from`, the block starts at the first line' -r
+complete -c sd -l backup -d 'Keep the original of each modified file next to it, with SUFFIX (`~` by default) added to its name. When that name is already taken, a number gets added after the suffix, as in `file~.1`. Files that end in SUFFIX are left out when searching directories' -r
+complete -c sd -s g -l glob -d 'Only replace in files matching GLOB, or leave out the ones matching it when it starts with `!`. May be repeated. Globs are matched against paths relative to the directory being searched, or against the path as given for files passed directly' -r
+complete -c sd -s t -l type -d 'Only replace in files of type TYPE, like `rust` or `js`. May be repeated. See `--type-list` for all of the types' -r
+complete -c sd -s T -l type-not -d 'Leave out files of type TYPE. May be repeated' -r
+complete -c sd -l type-add -d 'Add a file type or add a glob to an existing one, as in `--type-add \'web:*.{html,css}\'`. May be repeated' -r
+complete -c sd -l encoding -d 'Read and write text that doesn\'t start with a byte order mark as ENCODING, like `utf-16le` or `latin1`, rather than as UTF-8. Text that does start with one is always transcoded accordingly, keeping the byte order mark' -r
+complete -c sd -s p -l preview -d 'Display changes in a human reviewable format (the specifics of the format are likely to change in the future). For files, only the changed lines are shown, prefixed with their line numbers'
+complete -c sd -l check -d 'Don\'t change anything, only list the files that would change. Exits with a status of 2 if anything would change, including STDIN'
+complete -c sd -l diff -d 'Don\'t change anything, only print a unified diff of the changes that would be made'
 complete -c sd -s F -l fixed-strings -d 'Treat FIND and REPLACE_WITH args as literal strings'
+complete -c sd -l per-line -d 'Count the matches for `--skip`, `--every` and `--nth` separately on each line instead of across the whole input'
+complete -c sd -l simultaneous -d 'Apply all find & replace pairs together in a single left-to-right pass instead of one after another. Text produced by one replacement is never matched by another, so `-e foo bar -e bar foo` swaps the two words. When several patterns match at the same position, the one that was given first wins'
+complete -c sd -l line-by-line -d 'Replace STDIN one line at a time, writing out each line as soon as it\'s done. This already happens when none of the patterns can match a line break, and forcing it means that matches can\'t span lines'
+complete -c sd -l preserve-timestamps -d 'Keep the access and modification times of modified files as they were. Owners, groups, permissions, extended attributes and hard links are always kept where possible'
+complete -c sd -l json -d 'Print a JSON record for each match, with its path, byte offsets, line and column, matched text, capture groups and replacement, followed by a record for each file and a summary. One record is printed per line. Files still get replaced in unless `--preview` is passed as well. Offsets are into the text after it\'s decoded to UTF-8, and all of the patterns are matched in a single pass, like with `--simultaneous`. Replacing in files with more than one pattern therefore needs `--simultaneous` too'
+complete -c sd -l interactive -d 'Ask about each match before replacing it, showing the lines around it along with how it would change. Answer with y (yes), n (no), a (this and all of the rest), q (none of the rest) or e (type in what to replace it with instead). Answers are read from the terminal, or from STDIN when there\'s no terminal, and this only works on files. All of the patterns are matched in a single pass, so more than one pattern needs `--simultaneous`. Nothing gets changed until all of the questions are answered'
+complete -c sd -l atomic -d 'Replace in either all of the files or none of them. Every file gets replaced into a temporary file first, and only once all of them are ready do they take the place of the originals. If that fails for any of them, the ones already replaced are put back the way they were'
+complete -c sd -l journal -d 'Keep the originals of modified files in a journal, so that the run can be undone with `--undo`. Only the last run\'s journal is kept. It lives in the user\'s state directory, or in $SD_JOURNAL_DIR if set'
+complete -c sd -l undo -d 'Undo the last run that was made with `--journal`, putting back the originals of the files it modified. Nothing gets undone if any of the files were modified since'
+complete -c sd -l type-list -d 'Print all of the file types along with their globs, then exit'
+complete -c sd -l binary -d 'Replace in files that look binary too. By default, files found by searching directories are skipped with a warning when the start of the file has a NUL character. Files passed by name are never skipped'
+complete -c sd -l no-crlf -d 'Don\'t treat CRLF line breaks any differently. By default, when an input\'s first line break is a CRLF, `^` and `$` match on either side of the whole CRLF and line breaks in the replacement become CRLFs too. The same goes for the patterns of `--from` and `--to`. Pass this to strip the `\\r`s with `sd --no-crlf \'\\r$\' \'\'`'
+complete -c sd -s o -l only-matching -d 'Print only the replacement for each match, one per line, instead of the whole input. Files are left untouched. REPLACE_WITH defaults to the match itself (`$0`) when it\'s left out. Empty matches are skipped. All of the patterns are matched in a single pass, like with `--simultaneous`'
+complete -c sd -l with-filename -d 'Prefix each match printed by `--only-matching` with its file path'
+complete -c sd -l line-number -d 'Prefix each match printed by `--only-matching` with its line number'
 complete -c sd -s h -l help -d 'Print help (see more with \'--help\')'
 complete -c sd -s V -l version -d 'Print version'
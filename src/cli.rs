@@ -17,9 +17,45 @@ use clap::Parser;
 pub struct Options {
     #[arg(short, long)]
     /// Display changes in a human reviewable format (the specifics of the
-    /// format are likely to change in the future).
+    /// format are likely to change in the future). For files, only the
+    /// changed lines are shown, prefixed with their line numbers.
     pub preview: bool,
 
+    #[arg(short = 'C', long, value_name = "NUM", requires = "preview")]
+    /// Show NUM lines around each changed line in `--preview`. Only works
+    /// on files, since STDIN gets previewed whole.
+    pub context: Option<usize>,
+
+    #[arg(short = 'A', long, value_name = "NUM", requires = "preview")]
+    /// Show NUM lines after each changed line in `--preview`. Overrides
+    /// `--context`.
+    pub after_context: Option<usize>,
+
+    #[arg(short = 'B', long, value_name = "NUM", requires = "preview")]
+    /// Show NUM lines before each changed line in `--preview`. Overrides
+    /// `--context`.
+    pub before_context: Option<usize>,
+
+    #[arg(long, conflicts_with_all = ["preview", "only_matching"])]
+    /// Don't change anything, only list the files that would change. Exits
+    /// with a status of 2 if anything would change, including STDIN.
+    pub check: bool,
+
+    #[arg(long, conflicts_with_all = ["preview", "check", "only_matching"])]
+    /// Don't change anything, only print a unified diff of the changes that
+    /// would be made.
+    pub diff: bool,
+
+    #[arg(
+        short = 'U',
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        requires = "diff"
+    )]
+    /// Show N lines of context around each change in `--diff`.
+    pub unified: usize,
+
     #[arg(
         short = 'F',
         long = "fixed-strings",
@@ -39,6 +75,24 @@ pub struct Options {
     /// unlimited replacements.
     pub replacements: usize,
 
+    #[arg(long, value_name = "K", default_value_t)]
+    /// Leave the first K matches of each pattern alone.
+    pub skip: usize,
+
+    #[arg(long, value_name = "N")]
+    /// Replace only every Nth match of each pattern, starting with the first
+    /// one that isn't skipped by `--skip`.
+    pub every: Option<std::num::NonZeroUsize>,
+
+    #[arg(long, value_name = "N", conflicts_with_all = ["skip", "every"])]
+    /// Replace only the Nth match of each pattern.
+    pub nth: Option<std::num::NonZeroUsize>,
+
+    #[arg(long)]
+    /// Count the matches for `--skip`, `--every` and `--nth` separately on
+    /// each line instead of across the whole input.
+    pub per_line: bool,
+
     #[arg(short, long, verbatim_doc_comment)]
     #[rustfmt::skip]
     /** Regex flags. May be combined (like `-f mc`).
@@ -51,26 +105,268 @@ i - case-insensitive
 
 m - multi-line matching
 
+p - preserve case: match case-insensitively and adapt the replacement to the
+    casing of each match (e.g. lower, UPPER, Title, camelCase, snake_case)
+
 s - make `.` match newlines
 
 w - match full words only
     */
     pub flags: Option<String>,
 
+    #[arg(
+        short = 'e',
+        long = "expression",
+        num_args = 2,
+        value_names = ["FIND", "REPLACE_WITH"],
+        action = clap::ArgAction::Append
+    )]
+    /// Add a find & replace pair. May be repeated to apply several
+    /// replacements in order in a single pass over the input. When used, all
+    /// positional arguments are treated as file paths.
+    pub expressions: Vec<String>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Read find & replace rules from a file, one rule per line. Each rule is
+    /// written like sd's own arguments (`[-F] [-f FLAGS] [-n LIMIT] FIND
+    /// REPLACE_WITH`) with shell-like quoting, and lines starting with `#` are
+    /// comments. May be repeated. Rules from files are applied after any `-e`
+    /// pairs. When used, all positional arguments are treated as file paths.
+    pub rules: Vec<std::path::PathBuf>,
+
+    #[arg(long)]
+    /// Apply all find & replace pairs together in a single left-to-right pass
+    /// instead of one after another. Text produced by one replacement is
+    /// never matched by another, so `-e foo bar -e bar foo` swaps the two
+    /// words. When several patterns match at the same position, the one that
+    /// was given first wins.
+    pub simultaneous: bool,
+
+    #[arg(long)]
+    /// Replace STDIN one line at a time, writing out each line as soon as
+    /// it's done. This already happens when none of the patterns can match a
+    /// line break, and forcing it means that matches can't span lines.
+    pub line_by_line: bool,
+
+    #[arg(long, value_name = "BYTES")]
+    /// Replace files bigger than 8 MiB a piece at a time with a bounded
+    /// amount of memory by assuming that no match is longer than BYTES.
+    /// This already happens when none of the patterns can match a line
+    /// break. Longer matches spanning two pieces can be missed, which gets
+    /// warned about whenever a longer match turns up.
+    pub max_match_length: Option<usize>,
+
+    #[arg(long, value_name = "START,END", value_parser = parse_line_range)]
+    /// Only replace within lines START through END, counting from 1. Either
+    /// side can be left out (`10,` or `,20`), and a single number selects
+    /// just that line.
+    pub lines: Option<(usize, Option<usize>)>,
+
+    #[arg(long, value_name = "PATTERN")]
+    /// Only replace within blocks of lines that start with a line matching
+    /// the regex PATTERN, going up to the line matching `--to`. Without
+    /// `--to`, the block goes on until the end. Can be combined with
+    /// `--lines`.
+    pub from: Option<String>,
+
+    #[arg(long, value_name = "PATTERN")]
+    /// Only replace within blocks of lines that end with a line matching the
+    /// regex PATTERN. Without `--from`, the block starts at the first line.
+    pub to: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "~"
+    )]
+    /// Keep the original of each modified file next to it, with SUFFIX (`~`
+    /// by default) added to its name. When that name is already taken, a
+    /// number gets added after the suffix, as in `file~.1`. Files that end in
+    /// SUFFIX are left out when searching directories.
+    pub backup: Option<String>,
+
+    #[arg(long)]
+    /// Keep the access and modification times of modified files as they
+    /// were. Owners, groups, permissions, extended attributes and hard links
+    /// are always kept where possible.
+    pub preserve_timestamps: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["check", "diff", "only_matching", "interactive"]
+    )]
+    /// Print a JSON record for each match, with its path, byte offsets, line
+    /// and column, matched text, capture groups and replacement, followed by
+    /// a record for each file and a summary. One record is printed per line.
+    /// Files still get replaced in unless `--preview` is passed as well.
+    /// Offsets are into the text after it's decoded to UTF-8, and all of the
+    /// patterns are matched in a single pass, like with `--simultaneous`.
+    /// Replacing in files with more than one pattern therefore needs
+    /// `--simultaneous` too.
+    pub json: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["preview", "check", "diff", "only_matching"]
+    )]
+    /// Ask about each match before replacing it, showing the lines around it
+    /// along with how it would change. Answer with y (yes), n (no), a (this
+    /// and all of the rest), q (none of the rest) or e (type in what to
+    /// replace it with instead). Answers are read from the terminal, or from
+    /// STDIN when there's no terminal, and this only works on files. All of
+    /// the patterns are matched in a single pass, so more than one pattern
+    /// needs `--simultaneous`. Nothing gets changed until all of the
+    /// questions are answered.
+    pub interactive: bool,
+
+    #[arg(long)]
+    /// Replace in either all of the files or none of them. Every file gets
+    /// replaced into a temporary file first, and only once all of them are
+    /// ready do they take the place of the originals. If that fails for any
+    /// of them, the ones already replaced are put back the way they were.
+    pub atomic: bool,
+
+    #[arg(long)]
+    /// Keep the originals of modified files in a journal, so that the run
+    /// can be undone with `--undo`. Only the last run's journal is kept. It
+    /// lives in the user's state directory, or in $SD_JOURNAL_DIR if set.
+    pub journal: bool,
+
+    #[arg(long, exclusive = true)]
+    /// Undo the last run that was made with `--journal`, putting back the
+    /// originals of the files it modified. Nothing gets undone if any of the
+    /// files were modified since.
+    pub undo: bool,
+
+    #[arg(short, long, value_name = "GLOB")]
+    /// Only replace in files matching GLOB, or leave out the ones matching it
+    /// when it starts with `!`. May be repeated. Globs are matched against
+    /// paths relative to the directory being searched, or against the path
+    /// as given for files passed directly.
+    pub glob: Vec<String>,
+
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    /// Only replace in files of type TYPE, like `rust` or `js`. May be
+    /// repeated. See `--type-list` for all of the types.
+    pub file_types: Vec<String>,
+
+    #[arg(short = 'T', long = "type-not", value_name = "TYPE")]
+    /// Leave out files of type TYPE. May be repeated.
+    pub file_types_not: Vec<String>,
+
+    #[arg(long, value_name = "TYPE:GLOB")]
+    /// Add a file type or add a glob to an existing one, as in
+    /// `--type-add 'web:*.{html,css}'`. May be repeated.
+    pub type_add: Vec<String>,
+
+    #[arg(long)]
+    /// Print all of the file types along with their globs, then exit.
+    pub type_list: bool,
+
+    #[arg(long)]
+    /// Replace in files that look binary too. By default, files found by
+    /// searching directories are skipped with a warning when the start of
+    /// the file has a NUL character. Files passed by name are never skipped.
+    pub binary: bool,
+
+    #[arg(long, value_name = "ENCODING")]
+    /// Read and write text that doesn't start with a byte order mark as
+    /// ENCODING, like `utf-16le` or `latin1`, rather than as UTF-8. Text that
+    /// does start with one is always transcoded accordingly, keeping the byte
+    /// order mark.
+    pub encoding: Option<String>,
+
+    #[arg(long)]
+    /// Don't treat CRLF line breaks any differently. By default, when an
+    /// input's first line break is a CRLF, `^` and `$` match on either side
+    /// of the whole CRLF and line breaks in the replacement become CRLFs too.
+    /// The same goes for the patterns of `--from` and `--to`. Pass this to
+    /// strip the `\r`s with `sd --no-crlf '\r$' ''`.
+    pub no_crlf: bool,
+
+    #[arg(short, long)]
+    /// Print only the replacement for each match, one per line, instead of
+    /// the whole input. Files are left untouched. REPLACE_WITH defaults to
+    /// the match itself (`$0`) when it's left out. Empty matches are skipped.
+    /// All of the patterns are matched in a single pass, like with
+    /// `--simultaneous`.
+    pub only_matching: bool,
+
+    #[arg(long, requires = "only_matching")]
+    /// Prefix each match printed by `--only-matching` with its file path.
+    pub with_filename: bool,
+
+    #[arg(long, requires = "only_matching")]
+    /// Prefix each match printed by `--only-matching` with its line number.
+    pub line_number: bool,
+
+    #[arg(required_unless_present_any = [
+        "expressions",
+        "rules",
+        "type_list",
+        "undo",
+    ])]
     /// The regexp or string (if using `-F`) to search for.
-    pub find: String,
+    pub find: Option<String>,
 
+    #[arg(required_unless_present_any = [
+        "expressions",
+        "rules",
+        "only_matching",
+        "type_list",
+        "undo",
+    ])]
     /// What to replace each match with. Unless in string mode, you may
-    /// use captured values like $1, $2, etc.
-    pub replace_with: String,
+    /// use captured values like $1, $2, etc. and change the case of what
+    /// follows with \U (uppercase), \L (lowercase) or \E (stop), or of just
+    /// the next character with \u and \l.
+    pub replace_with: Option<String>,
 
     /// The path to file(s). This is optional - sd can also read from STDIN.
     ///
+    /// Directories are searched recursively, skipping hidden files and
+    /// anything ignored by `.gitignore`, `.ignore` or `.git/info/exclude`.
+    ///
     /// Note: sd modifies files in-place by default. See documentation for
     /// examples.
     pub files: Vec<std::path::PathBuf>,
 }
 
+/// Parses a range of lines like `10,20`, `10,`, `,20` or `10`
+fn parse_line_range(range: &str) -> Result<(usize, Option<usize>), String> {
+    let line_number = |n: &str| match n.parse() {
+        Ok(0) => Err("line numbers start at 1".to_owned()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("`{}` isn't a line number", n)),
+    };
+
+    let (start, end) = match range.split_once(',') {
+        Some(("", "")) => return Err("the range is empty".to_owned()),
+        Some((start, end)) => (
+            if start.is_empty() {
+                1
+            } else {
+                line_number(start)?
+            },
+            if end.is_empty() {
+                None
+            } else {
+                Some(line_number(end)?)
+            },
+        ),
+        None => {
+            let line = line_number(range)?;
+            (line, Some(line))
+        }
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err("the range ends before it starts".to_owned());
+    }
+    Ok((start, end))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,4 +378,15 @@ mod tests {
         let cmd = Options::command();
         cmd.debug_assert();
     }
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(parse_line_range("10,20"), Ok((10, Some(20))));
+        assert_eq!(parse_line_range("10,"), Ok((10, None)));
+        assert_eq!(parse_line_range(",20"), Ok((1, Some(20))));
+        assert_eq!(parse_line_range("7"), Ok((7, Some(7))));
+        for bad in [",", "0", "a,2", "3,2", "1,2,3"] {
+            assert!(parse_line_range(bad).is_err(), "{bad}");
+        }
+    }
 }
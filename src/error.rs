@@ -3,7 +3,7 @@ use std::{
     path::PathBuf,
 };
 
-use crate::replacer::InvalidReplaceCapture;
+use crate::{replacer::InvalidReplaceCapture, rules::InvalidRule};
 
 #[derive(thiserror::Error)]
 pub enum Error {
@@ -17,8 +17,60 @@ pub enum Error {
     InvalidPath(PathBuf),
     #[error("failed processing files:\n{0}")]
     FailedProcessing(FailedJobs),
+    #[error(
+        "no files were changed, since processing some of them failed:\n{0}"
+    )]
+    NothingChanged(FailedJobs),
     #[error("{0}")]
     InvalidReplaceCapture(#[from] InvalidReplaceCapture),
+    #[error("{0}")]
+    InvalidRule(#[from] InvalidRule),
+    #[error("failed to read rules file {}: {1}", .0.display())]
+    RulesFile(PathBuf, std::io::Error),
+    #[error("failed to walk directory: {0}")]
+    Walk(#[from] ignore::Error),
+    #[error("invalid glob: {0}")]
+    Glob(#[from] globset::Error),
+    #[error("invalid file type: {0}")]
+    FileType(ignore::Error),
+    #[error("unknown encoding: {0}")]
+    UnknownEncoding(String),
+    #[error("not valid {0} text")]
+    Decode(&'static str),
+    #[error("replaced text can't be encoded as {0}")]
+    Encode(&'static str),
+    #[error("couldn't find a directory to keep the journal in")]
+    NoJournalDir,
+    #[error("invalid journal: {0}")]
+    Journal(String),
+    #[error("there's no run to undo")]
+    NothingToUndo,
+    #[error("file changed since the last run")]
+    ChangedSinceRun,
+    #[error("can't undo the last run:\n{0}")]
+    CannotUndo(FailedJobs),
+    #[error(
+        "--interactive asks about each match on STDOUT, so it needs files"
+    )]
+    InteractiveStdin,
+    #[error(
+        "--interactive matches all of the patterns in a single pass, so it \
+         only works with a single pattern or --simultaneous"
+    )]
+    InteractiveSequentialRules,
+    #[error(
+        "--context, --after-context and --before-context only work on \
+         files, since STDIN gets previewed whole"
+    )]
+    ContextStdin,
+    #[error(
+        "--json matches all of the patterns in a single pass, so it only \
+         replaces in files with a single pattern or --simultaneous. Add -p \
+         to only report on the matches."
+    )]
+    JsonSequentialRules,
+    #[error("failed to write JSON: {0}")]
+    Json(String),
 }
 
 pub struct FailedJobs(Vec<(PathBuf, Error)>);
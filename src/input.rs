@@ -1,6 +1,17 @@
-use std::{fs::File, io::prelude::*, path::PathBuf};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
 
-use crate::{Error, Replacer, Result};
+use crate::{
+    interactive::{self, Prompt},
+    json,
+    preview::{self, Context},
+    replacer::{self, Progress, Replacement, Staged, WriteOptions},
+    Error, Replacer, Result,
+};
 
 use is_terminal::IsTerminal;
 
@@ -10,46 +21,668 @@ pub(crate) enum Source {
     Files(Vec<PathBuf>),
 }
 
+/// Prints just the replacement for each match instead of the whole input
+pub(crate) struct OnlyMatching {
+    pub(crate) with_filename: bool,
+    pub(crate) line_number: bool,
+}
+
+/// What gets done with the replacements
+pub(crate) enum OutputMode {
+    /// Replace in the files, or print the replaced text of STDIN
+    Replace,
+    /// Print just the lines that would change, with some context around them
+    Preview(Context),
+    OnlyMatching(OnlyMatching),
+    /// Ask about each match before replacing it
+    Interactive,
+    /// Report the matches as JSON Lines, replacing in the files as well when
+    /// `write` is set
+    Json {
+        write: bool,
+    },
+}
+
 pub(crate) struct App {
     replacer: Replacer,
     source: Source,
+    line_by_line: bool,
+    mode: OutputMode,
+    write: WriteOptions,
 }
 
 impl App {
     fn stdin_replace(&self, is_tty: bool) -> Result<()> {
-        let mut buffer = Vec::with_capacity(256);
         let stdin = std::io::stdin();
         let mut handle = stdin.lock();
+        // Text in another encoding than UTF-8 gets transcoded all at once
+        let is_transcoded = self.replacer.is_transcoded(handle.fill_buf()?);
+        if (self.line_by_line || self.replacer.is_line_local())
+            && !is_transcoded
+        {
+            drop(handle);
+            return self.stdin_replace_lines(is_tty);
+        }
+
+        let mut buffer = Vec::with_capacity(256);
         handle.read_to_end(&mut buffer)?;
 
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
 
-        handle.write_all(&if is_tty {
-            self.replacer.replace_preview(&buffer)
+        if is_tty {
+            let buffer = self.replacer.decode(&buffer)?;
+            handle.write_all(&self.replacer.replace_preview(&buffer))?;
         } else {
-            self.replacer.replace(&buffer)
+            handle.write_all(&self.replacer.replace_text(&buffer)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces stdin a batch of lines at a time, writing out each batch as
+    /// soon as it's done
+    ///
+    /// Lines are replaced without the line break that ends a batch, so this
+    /// matches replacing everything at once as long as no match can span
+    /// across lines. That includes the `\r` of a CRLF, as long as the input
+    /// uses those.
+    fn stdin_replace_lines(&self, is_tty: bool) -> Result<()> {
+        let stdin = std::io::stdin();
+        let mut reader = BufReader::with_capacity(64 * 1024, stdin.lock());
+        let stdout = std::io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+
+        let mut progress = self.replacer.progress();
+        // The start of a line that the last batch cut off
+        let mut partial = Vec::new();
+        loop {
+            let buffer = reader.fill_buf()?;
+            let len = buffer.len();
+            if len == 0 {
+                // Whatever comes after the last line break is a line too,
+                // even when it's empty
+                self.write_lines(&partial, &mut progress, is_tty, &mut writer)?;
+                break;
+            }
+
+            if let Some(end) = buffer.iter().rposition(|&b| b == b'\n') {
+                let lines = if partial.is_empty() {
+                    &buffer[..=end]
+                } else {
+                    partial.extend_from_slice(&buffer[..=end]);
+                    &partial[..]
+                };
+                self.write_lines(lines, &mut progress, is_tty, &mut writer)?;
+                partial.clear();
+                partial.extend_from_slice(&buffer[end + 1..]);
+            } else {
+                partial.extend_from_slice(buffer);
+            }
+            reader.consume(len);
+
+            // Only flush when we'd have to wait on more input anyways, so
+            // that slow streams show up right away without slowing down
+            // big ones
+            if reader.buffer().is_empty() {
+                writer.flush()?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes out `lines` replaced, keeping the line break they end in
+    fn write_lines(
+        &self,
+        lines: &[u8],
+        progress: &mut Progress,
+        is_tty: bool,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        let crlf = progress.crlf(lines);
+        let line_break: &[u8] = if crlf && lines.ends_with(b"\r\n") {
+            b"\r\n"
+        } else if lines.ends_with(b"\n") {
+            b"\n"
+        } else {
+            b""
+        };
+        let content = &lines[..lines.len() - line_break.len()];
+
+        writer.write_all(&if is_tty {
+            self.replacer.replace_preview_part(content, progress)
+        } else {
+            self.replacer.replace_part(content, progress)
         })?;
+        writer.write_all(line_break)?;
+        Ok(())
+    }
+
+    fn print_matches(&self, options: &OnlyMatching) -> Result<()> {
+        let stdout = std::io::stdout();
+        let mut handle = BufWriter::new(stdout.lock());
+
+        match &self.source {
+            Source::Stdin => {
+                let mut buffer = Vec::with_capacity(256);
+                std::io::stdin().lock().read_to_end(&mut buffer)?;
+                let buffer = self.replacer.decode(&buffer)?;
+                self.write_matches(&mut handle, options, None, &buffer)?;
+            }
+            Source::Files(paths) => {
+                let mut failed_jobs = Vec::new();
+                for path in paths {
+                    if let Err(e) =
+                        self.print_file_matches(&mut handle, options, path)
+                    {
+                        failed_jobs.push((path.to_owned(), e));
+                    }
+                }
+                handle.flush()?;
+                if !failed_jobs.is_empty() {
+                    let failed_jobs =
+                        crate::error::FailedJobs::from(failed_jobs);
+                    return Err(Error::FailedProcessing(failed_jobs));
+                }
+            }
+        }
 
+        handle.flush()?;
         Ok(())
     }
 
-    pub(crate) fn new(source: Source, replacer: Replacer) -> Self {
-        Self { source, replacer }
+    fn print_file_matches(
+        &self,
+        handle: &mut impl Write,
+        options: &OnlyMatching,
+        path: &Path,
+    ) -> Result<()> {
+        if Replacer::check_not_empty(File::open(path)?).is_err() {
+            return Ok(());
+        }
+        let file = unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+        let file = self.replacer.decode(&file)?;
+        self.write_matches(handle, options, Some(path), &file)
+    }
+
+    fn write_matches(
+        &self,
+        handle: &mut impl Write,
+        options: &OnlyMatching,
+        path: Option<&Path>,
+        content: &[u8],
+    ) -> Result<()> {
+        let replacements = self
+            .replacer
+            .replace_matches(content, &mut self.replacer.progress());
+
+        let mut line_number = 1;
+        let mut counted_to = 0;
+        for (matched, replacement) in replacements {
+            // Like `grep -o`, there's nothing to show for empty matches
+            if matched.is_empty() {
+                continue;
+            }
+            if let Some(path) = path.filter(|_| options.with_filename) {
+                write!(handle, "{}:", path.display())?;
+            }
+            if options.line_number {
+                line_number += content[counted_to..matched.start]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                counted_to = matched.start;
+                write!(handle, "{}:", line_number)?;
+            }
+            handle.write_all(&replacement)?;
+            handle.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn new(
+        source: Source,
+        replacer: Replacer,
+        line_by_line: bool,
+        mode: OutputMode,
+        write: WriteOptions,
+    ) -> Self {
+        Self {
+            source,
+            replacer,
+            line_by_line,
+            mode,
+            write,
+        }
     }
-    pub(crate) fn run(&self, preview: bool) -> Result<()> {
+
+    /// Prints a unified diff of the changes for each input without changing
+    /// anything, with `context` lines around each change
+    pub(crate) fn diff(&self, context: usize) -> Result<()> {
+        let stdout = std::io::stdout();
+        let mut handle = BufWriter::new(stdout.lock());
+
+        match &self.source {
+            Source::Stdin => {
+                let mut buffer = Vec::with_capacity(256);
+                std::io::stdin().lock().read_to_end(&mut buffer)?;
+                self.write_diff(&mut handle, context, "-", &buffer)?;
+            }
+            Source::Files(paths) => {
+                let mut failed_jobs = Vec::new();
+                for path in paths {
+                    if let Err(e) = self.diff_file(&mut handle, context, path) {
+                        failed_jobs.push((path.to_owned(), e));
+                    }
+                }
+                handle.flush()?;
+                if !failed_jobs.is_empty() {
+                    let failed_jobs =
+                        crate::error::FailedJobs::from(failed_jobs);
+                    return Err(Error::FailedProcessing(failed_jobs));
+                }
+            }
+        }
+
+        handle.flush()?;
+        Ok(())
+    }
+
+    fn diff_file(
+        &self,
+        handle: &mut impl Write,
+        context: usize,
+        path: &Path,
+    ) -> Result<()> {
+        if Replacer::check_not_empty(File::open(path)?).is_err() {
+            return Ok(());
+        }
+        let file = unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+        let name = path.display().to_string();
+        self.write_diff(handle, context, &name, &file)
+    }
+
+    fn write_diff(
+        &self,
+        handle: &mut impl Write,
+        context: usize,
+        name: &str,
+        content: &[u8],
+    ) -> Result<()> {
+        let original = self.replacer.decode(content)?;
+        let replaced = self.replacer.replace(&original);
+        if let Cow::Borrowed(_) = replaced {
+            return Ok(());
+        }
+
+        let original = String::from_utf8_lossy(&original);
+        let replaced = String::from_utf8_lossy(&replaced);
+        similar::TextDiff::from_lines(&*original, &*replaced)
+            .unified_diff()
+            .context_radius(context)
+            .header(name, name)
+            .to_writer(handle)?;
+        Ok(())
+    }
+
+    /// Lists the files that would change without changing anything, giving
+    /// back whether anything would
+    pub(crate) fn check(&self) -> Result<bool> {
+        match &self.source {
+            Source::Stdin => {
+                let mut buffer = Vec::with_capacity(256);
+                std::io::stdin().lock().read_to_end(&mut buffer)?;
+                Ok(*self.replacer.replace_text(&buffer)? != *buffer)
+            }
+            Source::Files(paths) => {
+                use rayon::prelude::*;
+
+                let results: Vec<_> = paths
+                    .par_iter()
+                    .map(|path| (path, self.replacer.would_change(path)))
+                    .collect();
+
+                let stdout = std::io::stdout();
+                let mut handle = stdout.lock();
+                let mut would_change = false;
+                let mut failed_jobs = Vec::new();
+                for (path, result) in results {
+                    match result {
+                        Ok(true) => {
+                            writeln!(handle, "{}", path.display())?;
+                            would_change = true;
+                        }
+                        Ok(false) => {}
+                        Err(e) => failed_jobs.push((path.to_owned(), e)),
+                    }
+                }
+
+                if failed_jobs.is_empty() {
+                    Ok(would_change)
+                } else {
+                    let failed_jobs =
+                        crate::error::FailedJobs::from(failed_jobs);
+                    Err(Error::FailedProcessing(failed_jobs))
+                }
+            }
+        }
+    }
+
+    /// Asks about each match in `paths` before replacing it, then replaces
+    /// the ones that were confirmed
+    ///
+    /// All of the questions get asked before any of the files are changed.
+    fn replace_interactively(&self, paths: &[PathBuf]) -> Result<()> {
+        // What gets confirmed has to be what a run without `--interactive`
+        // would write
+        if !self.replacer.is_single_pass() {
+            return Err(Error::InteractiveSequentialRules);
+        }
+        let stdout = std::io::stdout();
+        let mut prompt = Prompt::new(interactive::answers(), stdout.lock());
+
+        let mut results = Vec::new();
+        for path in paths {
+            if prompt.has_quit() {
+                break;
+            }
+            let confirmed = self.confirm(&mut prompt, path);
+            let staged = match confirmed {
+                Ok(confirmed) if confirmed.is_empty() => continue,
+                Ok(confirmed) => {
+                    self.replacer.stage_file_with(path, |content| {
+                        Some(replacer::apply(content, &confirmed))
+                    })
+                }
+                Err(e) => Err(e),
+            };
+            results.push((path.as_path(), staged));
+        }
+        drop(prompt);
+        self.commit(results)
+    }
+
+    /// Puts the staged files in place, either all of them or none of them
+    /// when the run is atomic
+    fn commit(
+        &self,
+        results: Vec<(&Path, Result<Option<Staged>>)>,
+    ) -> Result<()> {
+        if self.write.atomic {
+            return self.commit_atomically(results);
+        }
+        let failed_jobs = self.commit_each(results);
+        if failed_jobs.is_empty() {
+            Ok(())
+        } else {
+            let failed_jobs = crate::error::FailedJobs::from(failed_jobs);
+            Err(Error::FailedProcessing(failed_jobs))
+        }
+    }
+
+    /// Puts each of the staged files in place on its own, giving back the
+    /// ones that failed along with why
+    fn commit_each(
+        &self,
+        results: Vec<(&Path, Result<Option<Staged>>)>,
+    ) -> Vec<(PathBuf, Error)> {
+        results
+            .into_iter()
+            .filter_map(|(path, staged)| {
+                let committed = staged.and_then(|staged| match staged {
+                    Some(staged) => staged.commit(&self.write),
+                    None => Ok(()),
+                });
+                committed.err().map(|e| (path.to_owned(), e))
+            })
+            .collect()
+    }
+
+    /// Prints a JSON record for each match and each input, followed by a
+    /// summary, and replaces in the files as well if `write` is set
+    ///
+    /// What gets written is exactly what the records say, so all of the
+    /// patterns are matched in a single pass. Files only get written when
+    /// that's the same as a run without `--json`. STDIN only ever gets
+    /// reported on, since the records take the place of the replaced text.
+    fn print_json(&self, write: bool) -> Result<()> {
+        if write
+            && matches!(self.source, Source::Files(_))
+            && !self.replacer.is_single_pass()
+        {
+            return Err(Error::JsonSequentialRules);
+        }
+
+        let stdout = std::io::stdout();
+        let mut handle = BufWriter::new(stdout.lock());
+        let mut summary = json::Summary::default();
+
+        let mut results = Vec::new();
+        let mut failed_jobs = Vec::new();
+        match &self.source {
+            Source::Stdin => {
+                let mut buffer = Vec::with_capacity(256);
+                std::io::stdin().lock().read_to_end(&mut buffer)?;
+                self.write_json(&mut handle, None, &buffer, &mut summary)?;
+            }
+            Source::Files(paths) => {
+                for path in paths {
+                    let replacements =
+                        match self.json_file(&mut handle, path, &mut summary) {
+                            Ok(Some(replacements)) if write => replacements,
+                            Ok(_) => continue,
+                            Err(e) => {
+                                failed_jobs.push((path.to_owned(), e));
+                                continue;
+                            }
+                        };
+                    let staged =
+                        self.replacer.stage_file_with(path, |content| {
+                            Some(replacer::apply(content, &replacements))
+                        });
+                    results.push((path.as_path(), staged));
+                }
+            }
+        }
+
+        // The summary goes by what actually got written, so it comes last
+        let mut result = Ok(());
+        if write && matches!(self.source, Source::Files(_)) {
+            if !self.write.atomic {
+                let failed = self.commit_each(results);
+                // Only files that change get staged
+                summary.files_changed -= failed.len();
+                failed_jobs.extend(failed);
+                summary.written = true;
+            } else if failed_jobs.is_empty() {
+                result = self.commit_atomically(results);
+                summary.written = result.is_ok();
+            }
+        }
+        json::write(&mut handle, &json::Record::Summary(summary))?;
+        handle.flush()?;
+
+        if !failed_jobs.is_empty() {
+            let failed_jobs = crate::error::FailedJobs::from(failed_jobs);
+            return Err(if self.write.atomic {
+                Error::NothingChanged(failed_jobs)
+            } else {
+                Error::FailedProcessing(failed_jobs)
+            });
+        }
+        result
+    }
+
+    /// Writes the JSON records for the file at `path`, giving back its
+    /// replacements when they change it
+    fn json_file(
+        &self,
+        handle: &mut impl Write,
+        path: &Path,
+        summary: &mut json::Summary,
+    ) -> Result<Option<Vec<Replacement>>> {
+        if Replacer::check_not_empty(File::open(path)?).is_err() {
+            return Ok(None);
+        }
+        let file = unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+        self.write_json(handle, Some(path), &file, summary)
+    }
+
+    /// Writes the JSON records for a single input, giving back its
+    /// replacements when they change it
+    fn write_json(
+        &self,
+        handle: &mut impl Write,
+        path: Option<&Path>,
+        content: &[u8],
+        summary: &mut json::Summary,
+    ) -> Result<Option<Vec<Replacement>>> {
+        let content = self.replacer.decode(content)?;
+        let matches = self
+            .replacer
+            .find_matches(&content, &mut self.replacer.progress());
+        let replacements: Vec<_> = matches
+            .iter()
+            .map(|found| (found.range.clone(), found.replacement.clone()))
+            .collect();
+        let changed = replacer::apply(&content, &replacements) != *content;
+        json::write_matches(handle, path, &content, &matches, changed)?;
+
+        summary.files += 1;
+        summary.matches += matches.len();
+        if !changed {
+            return Ok(None);
+        }
+        summary.files_changed += 1;
+        Ok(Some(replacements))
+    }
+
+    /// Asks about each match in the file at `path`, giving back the
+    /// replacements that were confirmed
+    fn confirm(
+        &self,
+        prompt: &mut Prompt<impl BufRead, impl Write>,
+        path: &Path,
+    ) -> Result<Vec<Replacement>> {
+        if Replacer::check_not_empty(File::open(path)?).is_err() {
+            return Ok(Vec::new());
+        }
+        let file = unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+        let file = self.replacer.decode(&file)?;
+        let replacements = self
+            .replacer
+            .replace_matches(&file, &mut self.replacer.progress());
+        prompt.confirm(path, &file, replacements)
+    }
+
+    /// Replaces in either all of `paths` or none of them
+    ///
+    /// Every file gets replaced into a temporary file next to it before any
+    /// of them take the place of the originals. Should that fail for one of
+    /// them, the files that were already replaced get rolled back.
+    fn replace_atomically(&self, paths: &[PathBuf]) -> Result<()> {
+        use rayon::prelude::*;
+
+        let results: Vec<_> = paths
+            .par_iter()
+            .map(|path| (path.as_path(), self.replacer.stage_file(path)))
+            .collect();
+        self.commit_atomically(results)
+    }
+
+    /// Puts either all of the staged files in place or none of them
+    fn commit_atomically(
+        &self,
+        results: Vec<(&Path, Result<Option<Staged>>)>,
+    ) -> Result<()> {
+        let mut staged = Vec::new();
+        let mut failed_jobs = Vec::new();
+        for (path, result) in results {
+            match result {
+                Ok(Some(file)) if file.changed() => staged.push((path, file)),
+                Ok(_) => {}
+                Err(e) => failed_jobs.push((path.to_owned(), e)),
+            }
+        }
+        if !failed_jobs.is_empty() {
+            let failed_jobs = crate::error::FailedJobs::from(failed_jobs);
+            return Err(Error::NothingChanged(failed_jobs));
+        }
+
+        let mut undos = Vec::with_capacity(staged.len());
+        for (path, file) in staged {
+            match file.commit_undoable(&self.write) {
+                Ok(undo) => undos.push(undo),
+                Err(e) => {
+                    let mut failed_jobs = vec![(path.to_owned(), e)];
+                    let mut rolled_back = true;
+                    for undo in undos.into_iter().rev() {
+                        let path = undo.path().to_owned();
+                        if let Err(e) = undo.undo() {
+                            failed_jobs.push((path, e));
+                            rolled_back = false;
+                        }
+                    }
+
+                    let failed_jobs =
+                        crate::error::FailedJobs::from(failed_jobs);
+                    return Err(if rolled_back {
+                        if let Some(journal) = &self.write.journal {
+                            journal.clear();
+                        }
+                        Error::NothingChanged(failed_jobs)
+                    } else {
+                        Error::FailedProcessing(failed_jobs)
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Does whatever the output mode calls for with every input
+    pub(crate) fn run(self) -> Result<()> {
+        let result = self.replace();
+        // Whatever did get changed can be undone, even if not everything did
+        let journaled = match self.write.journal {
+            Some(journal) => journal.finish(),
+            None => Ok(()),
+        };
+        result.and(journaled)
+    }
+
+    fn replace(&self) -> Result<()> {
         let is_tty = std::io::stdout().is_terminal();
 
-        match (&self.source, preview) {
-            (Source::Stdin, true) => self.stdin_replace(is_tty),
-            (Source::Stdin, false) => self.stdin_replace(is_tty),
-            (Source::Files(paths), false) => {
+        match (&self.source, &self.mode) {
+            (_, OutputMode::OnlyMatching(options)) => {
+                self.print_matches(options)
+            }
+            (_, OutputMode::Json { write }) => self.print_json(*write),
+            (Source::Stdin, OutputMode::Interactive) => {
+                Err(Error::InteractiveStdin)
+            }
+            (Source::Stdin, _) => self.stdin_replace(is_tty),
+            (Source::Files(paths), OutputMode::Interactive) => {
+                self.replace_interactively(paths)
+            }
+            (Source::Files(paths), OutputMode::Replace)
+                if self.write.atomic =>
+            {
+                self.replace_atomically(paths)
+            }
+            (Source::Files(paths), OutputMode::Replace) => {
                 use rayon::prelude::*;
 
                 let failed_jobs: Vec<_> = paths
                     .par_iter()
                     .filter_map(|p| {
-                        if let Err(e) = self.replacer.replace_file(p) {
+                        if let Err(e) =
+                            self.replacer.replace_file(p, &self.write)
+                        {
                             Some((p.to_owned(), e))
                         } else {
                             None
@@ -65,17 +698,18 @@ impl App {
                     Err(Error::FailedProcessing(failed_jobs))
                 }
             }
-            (Source::Files(paths), true) => {
+            (Source::Files(paths), OutputMode::Preview(context)) => {
                 let stdout = std::io::stdout();
-                let mut handle = stdout.lock();
+                let mut handle = BufWriter::new(stdout.lock());
                 let print_path = paths.len() > 1;
 
-                paths.iter().try_for_each(|path| {
+                for path in paths {
                     if Replacer::check_not_empty(File::open(path)?).is_err() {
-                        return Ok(());
+                        continue;
                     }
                     let file =
                         unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+                    let file = self.replacer.decode(&file)?;
                     if self.replacer.has_matches(&file) {
                         if print_path {
                             writeln!(
@@ -85,13 +719,19 @@ impl App {
                             )?;
                         }
 
-                        handle
-                            .write_all(&self.replacer.replace_preview(&file))?;
-                        writeln!(handle)?;
+                        let (replaced, highlights) =
+                            self.replacer.replace_highlighted(&file);
+                        preview::write_changed_lines(
+                            &mut handle,
+                            &file,
+                            &replaced,
+                            &highlights,
+                            *context,
+                        )?;
                     }
-
-                    Ok(())
-                })
+                }
+                handle.flush()?;
+                Ok(())
             }
         }
     }
@@ -0,0 +1,221 @@
+//! Asking about each match before it gets replaced
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    ops::Range,
+    path::Path,
+};
+
+use ansi_term::Color;
+
+use crate::{replacer::Replacement, Result};
+
+/// How many lines get shown before and after each match
+const CONTEXT: usize = 2;
+
+const HELP: &str = "\
+y - replace this match
+n - leave this match alone
+a - replace this match and all of the ones after it
+q - leave this match and all of the ones after it alone
+e - replace this match with text that you type in instead
+? - show this help";
+
+/// Where the answers get read from: the terminal, so that they still can be
+/// when STDIN is taken up by something else like a list of files, or STDIN
+/// when there's no terminal to be had
+pub(crate) fn answers() -> Box<dyn BufRead> {
+    #[cfg(unix)]
+    let terminal = File::open("/dev/tty");
+    #[cfg(windows)]
+    let terminal = File::open("CONIN$");
+    #[cfg(not(any(unix, windows)))]
+    let terminal: std::io::Result<File> =
+        Err(std::io::ErrorKind::Unsupported.into());
+
+    match terminal {
+        Ok(terminal) => Box::new(BufReader::new(terminal)),
+        Err(_) => Box::new(std::io::stdin().lock()),
+    }
+}
+
+/// Where the answers to what gets replaced come from, and where the
+/// questions go
+pub(crate) struct Prompt<R, W> {
+    input: R,
+    output: W,
+    /// The answer that goes for all of the remaining matches, once there is
+    /// one
+    rest: Option<bool>,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub(crate) fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            rest: None,
+        }
+    }
+
+    /// Whether all of the remaining matches are to be left alone
+    pub(crate) fn has_quit(&self) -> bool {
+        self.rest == Some(false)
+    }
+
+    /// Asks about each of the `replacements` in `content`, giving back the
+    /// ones that should be made
+    pub(crate) fn confirm(
+        &mut self,
+        path: &Path,
+        content: &[u8],
+        replacements: Vec<Replacement>,
+    ) -> Result<Vec<Replacement>> {
+        let mut confirmed = Vec::new();
+        for (matched, replacement) in replacements {
+            let replacement = match self.rest {
+                Some(true) => Some(replacement),
+                Some(false) => None,
+                None => self.ask(path, content, &matched, replacement)?,
+            };
+            if let Some(replacement) = replacement {
+                confirmed.push((matched, replacement));
+            }
+        }
+        Ok(confirmed)
+    }
+
+    /// Shows a single match and asks about it until there's a valid answer,
+    /// giving back what it should be replaced with, if anything
+    fn ask(
+        &mut self,
+        path: &Path,
+        content: &[u8],
+        matched: &Range<usize>,
+        replacement: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.show(path, content, matched, &replacement)?;
+        loop {
+            write!(self.output, "Replace? [y,n,a,q,e,?] ")?;
+            self.output.flush()?;
+            let answer = self.read_line()?;
+            match answer.as_deref().map(str::trim) {
+                Some("y") => return Ok(Some(replacement)),
+                Some("n") => return Ok(None),
+                Some("a") => {
+                    self.rest = Some(true);
+                    return Ok(Some(replacement));
+                }
+                // Running out of answers is the same as quitting
+                Some("q") | None => {
+                    self.rest = Some(false);
+                    return Ok(None);
+                }
+                Some("e") => {
+                    write!(self.output, "Replace with: ")?;
+                    self.output.flush()?;
+                    let Some(text) = self.read_line()? else {
+                        self.rest = Some(false);
+                        return Ok(None);
+                    };
+                    return Ok(Some(text.into_bytes()));
+                }
+                Some(_) => writeln!(self.output, "{}", HELP)?,
+            }
+        }
+    }
+
+    /// Reads a single line without its line break, or `None` at the end of
+    /// the input
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+
+    /// Prints the lines around the match, followed by the lines it's on
+    /// both before and after being replaced
+    fn show(
+        &mut self,
+        path: &Path,
+        content: &[u8],
+        matched: &Range<usize>,
+        replacement: &[u8],
+    ) -> Result<()> {
+        let start = content[..matched.start]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let end = content[matched.end..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(content.len(), |i| matched.end + i);
+        let before: Vec<_> = lines(&content[..start]).collect();
+        let number = before.len() + 1;
+
+        let location = format!("{}:{}", path.display(), number);
+        writeln!(self.output, "{}", Color::Purple.paint(location))?;
+
+        let first = before.len().saturating_sub(CONTEXT);
+        for (i, line) in before.iter().enumerate().skip(first) {
+            self.write_line(' ', i + 1, line)?;
+        }
+
+        let highlighted = |color: Color, text: &[u8]| {
+            [
+                &content[start..matched.start],
+                color.prefix().to_string().as_bytes(),
+                text,
+                color.suffix().to_string().as_bytes(),
+                &content[matched.end..end],
+            ]
+            .concat()
+        };
+        let old = highlighted(Color::Red, &content[matched.clone()]);
+        let new = highlighted(Color::Green, replacement);
+        for (i, line) in lines(&old).enumerate() {
+            self.write_line('-', number + i, line)?;
+        }
+        for (i, line) in lines(&new).enumerate() {
+            self.write_line('+', number + i, line)?;
+        }
+
+        let number = number + lines(&content[start..end]).count();
+        let after = content.get(end + 1..).unwrap_or_default();
+        for (i, line) in lines(after).take(CONTEXT).enumerate() {
+            self.write_line(' ', number + i, line)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(
+        &mut self,
+        marker: char,
+        number: usize,
+        line: &[u8],
+    ) -> Result<()> {
+        // The `\r` of a CRLF would mess up the terminal
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        write!(self.output, "{} {:>4} | ", marker, number)?;
+        self.output.write_all(line)?;
+        writeln!(self.output)?;
+        Ok(())
+    }
+}
+
+/// The lines of `text`, without their line breaks
+fn lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    // There's no line after a line break at the very end, nor in no text
+    (!text.is_empty())
+        .then(|| {
+            let text = text.strip_suffix(b"\n").unwrap_or(text);
+            text.split(|&b| b == b'\n')
+        })
+        .into_iter()
+        .flatten()
+}
@@ -0,0 +1,247 @@
+//! Keeping the originals of the files that a run changes, so that the run can
+//! be undone later on
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+use crate::{error::FailedJobs, Error, Result};
+
+/// Overrides where journals are kept
+const DIR_VAR: &str = "SD_JOURNAL_DIR";
+const LAST_RUN: &str = "last-run";
+const MANIFEST: &str = "manifest.json";
+
+/// The journal of the run that's going on, which only takes the place of the
+/// last run's journal once it's finished
+pub(crate) struct Journal {
+    root: PathBuf,
+    /// Only created once the first file gets changed, so that runs that
+    /// don't change anything leave nothing behind
+    dir: Mutex<Option<TempDir>>,
+    entries: Mutex<Vec<Entry>>,
+}
+
+/// A single file that the run changed
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Entry {
+    #[serde(with = "os_path")]
+    path: PathBuf,
+    /// The name of the copy of the original within the journal
+    original: String,
+    /// The hash of what the file was changed to
+    sha256: String,
+}
+
+/// Where journals are kept: `$SD_JOURNAL_DIR`, or otherwise the user's state
+/// or local data directory
+fn root() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(DIR_VAR) {
+        return Ok(dir.into());
+    }
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("sd"))
+        .ok_or(Error::NoJournalDir)
+}
+
+/// Paths are kept as text where they're valid UTF-8, and otherwise as the
+/// bytes they're made up of, or their UTF-16 code units on Windows
+mod os_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        #[cfg(unix)]
+        Raw(Vec<u8>),
+        #[cfg(windows)]
+        Raw(Vec<u16>),
+    }
+
+    pub(super) fn serialize<S: Serializer>(
+        path: &Path,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let repr = match path.to_str() {
+            Some(text) => Repr::Text(text.to_owned()),
+            #[cfg(unix)]
+            None => {
+                use std::os::unix::ffi::OsStrExt;
+                Repr::Raw(path.as_os_str().as_bytes().to_vec())
+            }
+            #[cfg(windows)]
+            None => {
+                use std::os::windows::ffi::OsStrExt;
+                Repr::Raw(path.as_os_str().encode_wide().collect())
+            }
+            #[cfg(not(any(unix, windows)))]
+            None => Repr::Text(path.to_string_lossy().into_owned()),
+        };
+        repr.serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<PathBuf, D::Error> {
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Text(text) => text.into(),
+            #[cfg(unix)]
+            Repr::Raw(bytes) => {
+                use std::os::unix::ffi::OsStringExt;
+                std::ffi::OsString::from_vec(bytes).into()
+            }
+            #[cfg(windows)]
+            Repr::Raw(wide) => {
+                use std::os::windows::ffi::OsStringExt;
+                std::ffi::OsString::from_wide(&wide).into()
+            }
+        })
+    }
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl Journal {
+    pub(crate) fn new() -> Result<Self> {
+        Ok(Self {
+            root: root()?,
+            dir: Mutex::new(None),
+            entries: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Where the originals get copied to, creating it the first time
+    fn dir(&self) -> Result<PathBuf> {
+        let mut dir = self.dir.lock().unwrap();
+        if dir.is_none() {
+            fs::create_dir_all(&self.root)?;
+            *dir = Some(
+                tempfile::Builder::new()
+                    .prefix("run")
+                    .tempdir_in(&self.root)?,
+            );
+        }
+        Ok(dir.as_ref().unwrap().path().to_owned())
+    }
+
+    /// Copies the file at `path` into the journal before it gets changed to
+    /// the contents of the file at `replaced`
+    ///
+    /// The entry only becomes part of the journal once it's [added], which
+    /// should happen once the change is made.
+    ///
+    /// [added]: Self::add
+    pub(crate) fn keep(&self, path: &Path, replaced: &Path) -> Result<Entry> {
+        let (mut copy, copy_path) =
+            tempfile::Builder::new().tempfile_in(self.dir()?)?.keep()?;
+        io::copy(&mut File::open(path)?, &mut copy)?;
+        Ok(Entry {
+            path: path.to_path_buf(),
+            original: copy_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            sha256: sha256(replaced)?,
+        })
+    }
+
+    pub(crate) fn add(&self, entry: Entry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Forgets about every change, for when they were all rolled back
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Makes this the journal of the last run, unless nothing was changed
+    pub(crate) fn finish(self) -> Result<()> {
+        let entries = self.entries.into_inner().unwrap();
+        let Some(dir) = self.dir.into_inner().unwrap() else {
+            return Ok(());
+        };
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let manifest = serde_json::to_vec_pretty(&entries)
+            .map_err(|e| Error::Journal(e.to_string()))?;
+        fs::write(dir.path().join(MANIFEST), manifest)?;
+
+        let last_run = self.root.join(LAST_RUN);
+        if last_run.exists() {
+            fs::remove_dir_all(&last_run)?;
+        }
+        // Once it's moved, there's nothing left for the `TempDir` to clean up
+        fs::rename(dir.path(), last_run)?;
+        Ok(())
+    }
+}
+
+/// Puts back the originals of every file that the last run with a journal
+/// changed, as long as none of them changed since
+pub(crate) fn undo() -> Result<()> {
+    let last_run = root()?.join(LAST_RUN);
+    let manifest = match fs::read(last_run.join(MANIFEST)) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NothingToUndo)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let entries: Vec<Entry> = serde_json::from_slice(&manifest)
+        .map_err(|e| Error::Journal(e.to_string()))?;
+
+    let changed: Vec<_> = entries
+        .iter()
+        .filter_map(|entry| match sha256(&entry.path) {
+            Ok(sha256) if sha256 == entry.sha256 => None,
+            Ok(_) => Some((entry.path.clone(), Error::ChangedSinceRun)),
+            Err(e) => Some((entry.path.clone(), e.into())),
+        })
+        .collect();
+    if !changed.is_empty() {
+        return Err(Error::CannotUndo(FailedJobs::from(changed)));
+    }
+
+    // Writing over the files keeps their metadata and hard links intact
+    let mut failed_jobs = Vec::new();
+    for entry in entries {
+        let restored = File::open(last_run.join(&entry.original)).and_then(
+            |mut original| {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .truncate(true)
+                    .open(&entry.path)?;
+                io::copy(&mut original, &mut file)
+            },
+        );
+        if let Err(e) = restored {
+            failed_jobs.push((entry.path, e.into()));
+        }
+    }
+    if !failed_jobs.is_empty() {
+        return Err(Error::FailedProcessing(FailedJobs::from(failed_jobs)));
+    }
+
+    fs::remove_dir_all(last_run)?;
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Reporting matches and replacements as JSON Lines, for other tools to pick
+//! up
+
+use std::{borrow::Cow, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    replacer::{Group, Match},
+    Error, Result,
+};
+
+/// A single line of output
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Record<'a> {
+    /// A match along with what it gets replaced with
+    Match {
+        path: Option<Cow<'a, str>>,
+        /// Byte offsets into the input, after it's decoded to UTF-8
+        start: usize,
+        end: usize,
+        /// Counting from 1, with the column in bytes
+        line: usize,
+        column: usize,
+        matched: Cow<'a, str>,
+        captures: Vec<Option<Capture<'a>>>,
+        replacement: Cow<'a, str>,
+    },
+    /// How a single input went, after all of its matches
+    File {
+        path: Option<Cow<'a, str>>,
+        matches: usize,
+        changed: bool,
+    },
+    /// How the whole run went, after everything else
+    Summary(Summary),
+}
+
+/// A capture group that took part in a match
+#[derive(Serialize)]
+pub(crate) struct Capture<'a> {
+    name: Option<&'a str>,
+    start: usize,
+    end: usize,
+    text: Cow<'a, str>,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct Summary {
+    pub(crate) files: usize,
+    pub(crate) files_changed: usize,
+    pub(crate) matches: usize,
+    /// Whether the changed files were written to, rather than only reported,
+    /// in which case `files_changed` only counts the ones that were
+    pub(crate) written: bool,
+}
+
+/// Writes out a single record on a line of its own
+pub(crate) fn write(handle: &mut impl Write, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *handle, record)
+        .map_err(|e| Error::Json(e.to_string()))?;
+    writeln!(handle)?;
+    Ok(())
+}
+
+/// Writes out a record for each of the `matches` in `content`, followed by
+/// the record for the whole input
+///
+/// The input is STDIN when there's no `path`.
+pub(crate) fn write_matches(
+    handle: &mut impl Write,
+    path: Option<&Path>,
+    content: &[u8],
+    matches: &[Match],
+    changed: bool,
+) -> Result<()> {
+    let path = path.map(Path::to_string_lossy);
+    let text = |range: &std::ops::Range<usize>| {
+        String::from_utf8_lossy(&content[range.clone()])
+    };
+
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut counted_to = 0;
+    for found in matches {
+        for (i, _) in content[counted_to..found.range.start]
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'\n')
+        {
+            line += 1;
+            line_start = counted_to + i + 1;
+        }
+        counted_to = found.range.start;
+
+        let captures = found
+            .groups
+            .iter()
+            .map(|Group { name, range }| {
+                range.as_ref().map(|range| Capture {
+                    name: *name,
+                    start: range.start,
+                    end: range.end,
+                    text: text(range),
+                })
+            })
+            .collect();
+        let record = Record::Match {
+            path: path.clone(),
+            start: found.range.start,
+            end: found.range.end,
+            line,
+            column: found.range.start - line_start + 1,
+            matched: text(&found.range),
+            captures,
+            replacement: String::from_utf8_lossy(&found.replacement),
+        };
+        write(handle, &record)?;
+    }
+
+    write(
+        handle,
+        &Record::File {
+            path,
+            matches: matches.len(),
+            changed,
+        },
+    )
+}
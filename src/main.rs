@@ -1,19 +1,30 @@
 mod cli;
 mod error;
 mod input;
+mod interactive;
+mod journal;
+mod json;
+mod preview;
+mod rules;
+mod walk;
 
 pub(crate) mod replacer;
 pub(crate) mod utils;
 
-use std::process;
+use std::{num::NonZeroUsize, path::PathBuf, process};
 
-pub(crate) use self::input::{App, Source};
+pub(crate) use self::input::{App, OnlyMatching, OutputMode, Source};
 use ansi_term::{Color, Style};
 pub(crate) use error::{Error, Result};
-use replacer::Replacer;
+use replacer::{
+    Occurrences, Replacer, ReplacerOptions, Rule, RuleOptions, WriteOptions,
+};
 
 use clap::Parser;
 
+/// The exit status for `--check` when something would change
+const CHECK_FAILED: i32 = 2;
+
 fn main() {
     if let Err(e) = try_main() {
         eprintln!("{}: {}", Style::from(Color::Red).bold().paint("error"), e);
@@ -23,23 +34,191 @@ fn main() {
 
 fn try_main() -> Result<()> {
     let options = cli::Options::parse();
+    if options.undo {
+        return journal::undo();
+    }
+
+    let file_types = walk::FileTypes {
+        select: &options.file_types,
+        negate: &options.file_types_not,
+        add: &options.type_add,
+    };
+    if options.type_list {
+        for (name, globs) in file_types.list()? {
+            println!("{}: {}", name, globs.join(", "));
+        }
+        return Ok(());
+    }
+
+    let mut files = options.files;
+    let pairs = if options.expressions.is_empty() && options.rules.is_empty() {
+        // clap makes these required when no `-e` pairs or rules are passed,
+        // except for REPLACE_WITH when only printing the matches
+        let find = options.find.unwrap();
+        let replace_with = options.replace_with.unwrap_or_else(|| {
+            if options.literal_mode {
+                find.clone()
+            } else {
+                "$0".to_owned()
+            }
+        });
+        vec![(find, replace_with)]
+    } else {
+        // With `-e` or `--rules` there are no positional find & replace args,
+        // so anything that landed there is actually a file path
+        let paths = options.find.into_iter().chain(options.replace_with);
+        files.splice(0..0, paths.map(PathBuf::from));
+
+        options
+            .expressions
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect()
+    };
+
+    let occurrences = match options.nth {
+        Some(nth) => Occurrences {
+            skip: nth.get() - 1,
+            take: Some(1),
+            per_line: options.per_line,
+            ..Occurrences::default()
+        },
+        None => Occurrences {
+            skip: options.skip,
+            every: options.every.map_or(1, NonZeroUsize::get),
+            per_line: options.per_line,
+            ..Occurrences::default()
+        },
+    };
+
+    let rule_options = RuleOptions {
+        literal: options.literal_mode,
+        flags: options.flags,
+        replacements: options.replacements,
+        occurrences,
+    };
+    let mut rules = pairs
+        .into_iter()
+        .map(|(find, replace_with)| {
+            Rule::new(find, replace_with, &rule_options)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for path in &options.rules {
+        rules.extend(rules::parse_file(path, &rule_options)?);
+    }
+
+    let encoding = options
+        .encoding
+        .as_deref()
+        .map(|label| {
+            encoding_rs::Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| Error::UnknownEncoding(label.to_owned()))
+        })
+        .transpose()?;
 
-    let source = if !options.files.is_empty() {
-        Source::Files(options.files)
+    let source = if !files.is_empty() {
+        let filter = walk::Filter::new(
+            &options.glob,
+            &file_types,
+            options.backup.as_deref(),
+        )?;
+        // Only files found in directories get skipped for looking binary,
+        // since the ones asked for by name are meant to be replaced in
+        let (mut files, walked) = walk::files(files, &filter)?;
+        if options.binary {
+            files.extend(walked);
+        } else {
+            let (text, binary) = walk::split_binary(walked, encoding);
+            files.extend(text);
+            for path in binary {
+                eprintln!(
+                    "{}: skipping binary file {}",
+                    Style::from(Color::Yellow).bold().paint("warning"),
+                    path.display()
+                );
+            }
+        }
+        Source::Files(files)
     } else {
         Source::Stdin
     };
 
-    App::new(
+    let address = if options.lines.is_some()
+        || options.from.is_some()
+        || options.to.is_some()
+    {
+        Some(replacer::Address::new(
+            options
+                .lines
+                .map(|(start, end)| start..=end.unwrap_or(usize::MAX)),
+            options.from.as_deref(),
+            options.to.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    let mode = if options.only_matching {
+        OutputMode::OnlyMatching(OnlyMatching {
+            with_filename: options.with_filename,
+            line_number: options.line_number,
+        })
+    } else if options.json {
+        OutputMode::Json {
+            write: !options.preview,
+        }
+    } else if options.interactive {
+        OutputMode::Interactive
+    } else if options.preview {
+        // STDIN gets previewed whole, so there's no context to pick
+        let has_context = options.context.is_some()
+            || options.after_context.is_some()
+            || options.before_context.is_some();
+        if has_context && matches!(source, Source::Stdin) {
+            return Err(Error::ContextStdin);
+        }
+        let context = options.context.unwrap_or(0);
+        OutputMode::Preview(preview::Context {
+            before: options.before_context.unwrap_or(context),
+            after: options.after_context.unwrap_or(context),
+        })
+    } else {
+        OutputMode::Replace
+    };
+
+    let app = App::new(
         source,
         Replacer::new(
-            options.find,
-            options.replace_with,
-            options.literal_mode,
-            options.flags,
-            options.replacements,
-        )?,
-    )
-    .run(options.preview)?;
+            rules,
+            ReplacerOptions {
+                simultaneous: options.simultaneous,
+                max_match_len: options.max_match_length,
+                address,
+                encoding,
+                no_crlf: options.no_crlf,
+            },
+        ),
+        options.line_by_line,
+        mode,
+        WriteOptions {
+            backup: options.backup,
+            preserve_timestamps: options.preserve_timestamps,
+            atomic: options.atomic,
+            journal: options.journal.then(journal::Journal::new).transpose()?,
+        },
+    );
+
+    if options.check {
+        if app.check()? {
+            process::exit(CHECK_FAILED);
+        }
+        return Ok(());
+    }
+
+    if options.diff {
+        return app.diff(options.unified);
+    }
+
+    app.run()?;
     Ok(())
 }
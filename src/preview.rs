@@ -0,0 +1,123 @@
+//! Previewing replacements by printing just the lines they changed, like
+//! ripgrep does for its matches
+
+use std::{io::Write, ops::Range};
+
+use ansi_term::Color;
+
+use crate::replacer::{original_pos, Edit};
+
+/// How many unchanged lines get printed around each changed one
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Context {
+    pub(crate) before: usize,
+    pub(crate) after: usize,
+}
+
+/// Writes the lines of `replaced` that have any of the `highlights` in them,
+/// each prefixed with its line number and with the highlights colored
+///
+/// Changed lines get a `:` after their number and context lines a `-`. When
+/// there's any context, `--` separates lines that aren't next to each other.
+/// Line numbers are those of the lines in `original` that each line came
+/// from, so lines that a replacement split up share the same number.
+pub(crate) fn write_changed_lines(
+    handle: &mut impl Write,
+    original: &[u8],
+    replaced: &[u8],
+    highlights: &[Edit],
+    context: Context,
+) -> std::io::Result<()> {
+    let mut lines = lines(replaced);
+    let changed: Vec<bool> = lines
+        .iter()
+        .map(|line| highlights_in(highlights, line).next().is_some())
+        .collect();
+    // Text that ends with a line break doesn't have another line after it
+    if lines.last().is_some_and(|line| line.is_empty())
+        && changed.last() == Some(&false)
+    {
+        lines.pop();
+    }
+
+    // Whether each line gets printed, with the context spread out from the
+    // changed lines
+    let mut shown = vec![false; lines.len()];
+    for (i, _) in changed.iter().enumerate().filter(|(_, &changed)| changed) {
+        let start = i.saturating_sub(context.before);
+        let end = (i + context.after + 1).min(lines.len());
+        shown[start..end].iter_mut().for_each(|shown| *shown = true);
+    }
+
+    let has_context = context.before > 0 || context.after > 0;
+    let mut last_shown = None;
+    // Counting line breaks in the original goes on from where it left off
+    let (mut number, mut counted_to) = (1, 0);
+    for (i, line) in lines.iter().enumerate().filter(|&(i, _)| shown[i]) {
+        if has_context && last_shown.is_some_and(|last| last + 1 < i) {
+            writeln!(handle, "--")?;
+        }
+        last_shown = Some(i);
+
+        let start = original_pos(highlights, line.start);
+        number += original[counted_to..start]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+        counted_to = start;
+
+        let separator = if changed[i] { ':' } else { '-' };
+        let gutter = Color::Green.paint(number.to_string());
+        write!(handle, "{}{}", gutter, separator)?;
+
+        // The `\r` of a CRLF would mess up the terminal
+        let end = match replaced[line.clone()] {
+            [.., b'\r'] => line.end - 1,
+            _ => line.end,
+        };
+        let mut last_end = line.start;
+        for span in highlights_in(highlights, line) {
+            // Highlights can go on past either end of the line
+            let start = span.start.clamp(last_end, end);
+            let span_end = span.end.clamp(start, end);
+            handle.write_all(&replaced[last_end..start])?;
+            write!(handle, "{}", Color::Blue.prefix())?;
+            handle.write_all(&replaced[start..span_end])?;
+            write!(handle, "{}", Color::Blue.suffix())?;
+            last_end = span_end;
+        }
+        handle.write_all(&replaced[last_end..end])?;
+        writeln!(handle)?;
+    }
+    Ok(())
+}
+
+/// Where each line of `text` is, without its line break
+fn lines(text: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (end, _) in text.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+        lines.push(start..end);
+        start = end + 1;
+    }
+    lines.push(start..text.len());
+    lines
+}
+
+/// The highlights that touch `line`, including its line break
+///
+/// A highlight that's empty, because a match got replaced with nothing, still
+/// counts for the line it's at.
+fn highlights_in<'a>(
+    highlights: &'a [Edit],
+    line: &Range<usize>,
+) -> impl Iterator<Item = &'a Range<usize>> {
+    let end = |span: &Range<usize>| span.end.max(span.start + 1);
+    // The highlights never overlap, so they're sorted by both of their ends
+    let first = highlights.partition_point(|edit| end(&edit.new) <= line.start);
+    let line_end = line.end;
+    highlights[first..]
+        .iter()
+        .map(|edit| &edit.new)
+        .take_while(move |span| span.start <= line_end)
+}
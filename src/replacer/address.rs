@@ -0,0 +1,103 @@
+use std::ops::{Range, RangeInclusive};
+
+use regex::bytes::Regex;
+
+use crate::Result;
+
+/// Which lines of the input the rules are applied to, like sed's addresses
+///
+/// Lines have to be both within the range of line numbers and within a block
+/// going from a line matching `from` up to the next line matching `to`.
+/// Leaving out `from` starts the block at the first line, and leaving out `to`
+/// keeps it going until the end of the input.
+pub(crate) struct Address {
+    lines: Option<RangeInclusive<usize>>,
+    from: Option<Regex>,
+    to: Option<Regex>,
+}
+
+/// Where the last piece of an input left off
+pub(crate) struct AddressState {
+    /// How many lines were seen so far
+    line: usize,
+    in_block: bool,
+}
+
+impl Address {
+    pub(crate) fn new(
+        lines: Option<RangeInclusive<usize>>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            lines,
+            from: from.map(Regex::new).transpose()?,
+            to: to.map(Regex::new).transpose()?,
+        })
+    }
+
+    pub(crate) fn start(&self) -> AddressState {
+        AddressState {
+            line: 0,
+            in_block: self.from.is_none(),
+        }
+    }
+
+    /// Whether the next line is selected, with `line` not including its
+    /// line break
+    fn select(&self, line: &[u8], state: &mut AddressState) -> bool {
+        state.line += 1;
+        let in_lines = self
+            .lines
+            .as_ref()
+            .map_or(true, |lines| lines.contains(&state.line));
+
+        let in_block = if state.in_block {
+            // The line that starts a block never ends it, same as with sed
+            if self.to.as_ref().is_some_and(|to| to.is_match(line)) {
+                state.in_block = false;
+            }
+            true
+        } else {
+            state.in_block =
+                self.from.as_ref().is_some_and(|from| from.is_match(line));
+            state.in_block
+        };
+
+        in_lines && in_block
+    }
+
+    /// The parts of `content` that the rules should be applied to
+    ///
+    /// `content` is taken to be made up of whole lines. Each run of selected
+    /// lines makes up a single region, so matches can span the lines within
+    /// it but never the line break right after it. With `crlf`, the `\r` at
+    /// the end of each line counts as part of its line break, same as for
+    /// the rules.
+    pub(crate) fn regions(
+        &self,
+        content: &[u8],
+        state: &mut AddressState,
+        crlf: bool,
+    ) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        let mut start = 0;
+        for line in content.split(|&b| b == b'\n') {
+            let end = start + line.len();
+            let text = match line {
+                [text @ .., b'\r'] if crlf => text,
+                _ => line,
+            };
+            if self.select(text, state) {
+                match regions.last_mut() {
+                    Some(region) if region.end + 1 == start => {
+                        region.end = end;
+                    }
+                    _ => regions.push(start..end),
+                }
+            }
+            start = end + 1;
+        }
+        regions
+    }
+}
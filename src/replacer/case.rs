@@ -0,0 +1,120 @@
+/// How the letters in a piece of text are cased
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Style {
+    /// `user`, `user_name`
+    Lower,
+    /// `USER`, `USER_NAME`
+    Upper,
+    /// `User`, `User_name`
+    Title,
+    /// `userName`
+    Camel,
+    /// `UserName`, `User_Name`
+    Pascal,
+}
+
+impl Style {
+    fn detect(text: &str) -> Option<Self> {
+        let mut letters = text.chars().filter(|c| c.is_alphabetic());
+        let first = letters.next()?;
+        let rest: Vec<_> = letters.collect();
+        let has_upper = rest.iter().any(|c| c.is_uppercase());
+        let has_lower = rest.iter().any(|c| c.is_lowercase());
+
+        let style = if first.is_uppercase() {
+            if !has_lower && !rest.is_empty() {
+                Self::Upper
+            } else if has_upper {
+                Self::Pascal
+            } else {
+                Self::Title
+            }
+        } else if has_upper {
+            Self::Camel
+        } else {
+            Self::Lower
+        };
+        Some(style)
+    }
+
+    fn apply_to_word(self, word: &str, is_first: bool) -> String {
+        match self {
+            Self::Lower => word.to_lowercase(),
+            Self::Upper => word.to_uppercase(),
+            Self::Title if is_first => capitalize(&word.to_lowercase()),
+            Self::Title => word.to_lowercase(),
+            Self::Camel if is_first => word.to_lowercase(),
+            Self::Camel | Self::Pascal => capitalize(&word.to_lowercase()),
+        }
+    }
+}
+
+/// Adapts the casing of `replacement` to look like the text it replaces
+///
+/// The overall style (lower, UPPER, Title, camelCase or PascalCase) gets
+/// picked up from `matched`. When `matched` is made up of several words, either
+/// through `_`/`-` separators or camel casing, then the words of `replacement`
+/// get joined back together in the same way.
+pub(crate) fn preserve_case(matched: &str, replacement: &str) -> String {
+    let Some(style) = Style::detect(matched) else {
+        return replacement.to_owned();
+    };
+    let separator = matched
+        .trim_matches(is_separator)
+        .chars()
+        .find(|&c| is_separator(c));
+
+    match (style, separator) {
+        (Style::Lower, None) => replacement.to_lowercase(),
+        (Style::Upper, None) => replacement.to_uppercase(),
+        (Style::Title, None) => capitalize(replacement),
+        (style, separator) => {
+            let words = split_words(replacement);
+            let mut joined = String::with_capacity(replacement.len());
+            for (i, word) in words.iter().enumerate() {
+                if i > 0 {
+                    joined.extend(separator);
+                }
+                joined.push_str(&style.apply_to_word(word, i == 0));
+            }
+            joined
+        }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '_' || c == '-'
+}
+
+/// Splits text at `_`/`-` separators and lowercase-to-uppercase boundaries
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        if is_separator(c) {
+            if start < i {
+                words.push(&text[start..i]);
+            }
+            start = i + c.len_utf8();
+        } else if c.is_uppercase()
+            && prev.is_some_and(|p| p.is_lowercase() || p.is_numeric())
+        {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        prev = Some(c);
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
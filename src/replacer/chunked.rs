@@ -0,0 +1,217 @@
+use std::io::{self, Read, Write};
+
+use super::{
+    detect_crlf, simultaneous::SimultaneousMatches, start_line, Count,
+    Replacer, Rule,
+};
+
+/// How much of a file gets read in at a time
+pub(super) const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How much already replaced text gets kept in front of the rest of the
+/// input. Look-behind assertions like `^` and `\b` only ever need the
+/// character right before a match, which is at most 4 bytes of UTF-8.
+const CONTEXT: usize = 4;
+
+/// One step of replacing an input a window at a time
+///
+/// Sequential rules each get their own stage that's fed the output of the
+/// one before it, while simultaneous rules all share a single stage.
+struct Stage<'r> {
+    rules: &'r [Rule],
+    counts: Vec<Count>,
+    /// The input that can't be replaced yet, preceded by `context` bytes of
+    /// input that already was
+    buf: Vec<u8>,
+    context: usize,
+    /// Whether the last match ended right where the remaining input starts
+    matched_to_start: bool,
+    /// Whether the input's lines end in CRLF, once that's known
+    crlf: Option<bool>,
+    /// How long a line can get before matches on it are assumed to be no
+    /// longer than this, which keeps the buffer from growing without bound
+    long_line: usize,
+    /// Whether a match was longer than matches were assumed to be
+    overlong: bool,
+}
+
+/// How replacing an input a window at a time went
+pub(crate) struct Chunked {
+    pub(crate) changed: bool,
+    /// Whether any match was longer than matches were assumed to be, so that
+    /// others that spanned two windows may have been missed
+    pub(crate) overlong: bool,
+}
+
+impl<'r> Stage<'r> {
+    fn new(rules: &'r [Rule], crlf: Option<bool>, long_line: usize) -> Self {
+        Self {
+            rules,
+            counts: vec![Count::default(); rules.len()],
+            buf: Vec::new(),
+            context: 0,
+            matched_to_start: false,
+            crlf,
+            long_line,
+            overlong: false,
+        }
+    }
+
+    /// The longest that a match is assumed to be, if anything
+    ///
+    /// Without `max_match_len`, a line that goes on for too long without a
+    /// line break gets treated as if matches on it were at most `long_line`
+    /// bytes long. The alternative is holding it all in memory.
+    fn max_match_len(&self, max_match_len: Option<usize>) -> Option<usize> {
+        max_match_len.or_else(|| {
+            (self.buf.len() > 2 * self.long_line
+                && !self.buf[self.context..].contains(&b'\n'))
+            .then_some(self.long_line)
+        })
+    }
+
+    /// Where the input stops being safe to replace, since a match starting
+    /// any later could still depend on input that hasn't been seen yet
+    fn safe_end(&self, max_match_len: Option<usize>) -> usize {
+        let by_line = self
+            .rules
+            .iter()
+            .all(|rule| rule.is_line_local)
+            .then(|| {
+                self.buf[self.context..]
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map(|i| self.context + i + 1)
+            })
+            .flatten();
+        let by_len = max_match_len.map(|len| {
+            self.buf.len().saturating_sub(len.saturating_add(CONTEXT))
+        });
+        by_line.max(by_len).unwrap_or(0).max(self.context)
+    }
+
+    /// Takes in the next piece of the input and writes out everything that
+    /// can be replaced so far. Once `eof` is set the rest gets written out.
+    fn feed(
+        &mut self,
+        input: &[u8],
+        eof: bool,
+        max_match_len: Option<usize>,
+        out: &mut Vec<u8>,
+    ) {
+        self.buf.extend_from_slice(input);
+        let start = self.context;
+        let max_match_len = self.max_match_len(max_match_len);
+        let end = if eof {
+            usize::MAX
+        } else {
+            self.safe_end(max_match_len)
+        };
+        if end == start {
+            return;
+        }
+
+        if self.crlf.is_none() {
+            self.crlf = detect_crlf(&self.buf);
+        }
+        let crlf = self.crlf.unwrap_or(false);
+        let mut matches = SimultaneousMatches::new(
+            self.rules,
+            &self.buf,
+            &mut self.counts,
+            crlf,
+        )
+        .within(start, end, self.matched_to_start.then_some(start));
+        let mut last_match = start;
+        for (rule, caps) in matches.by_ref() {
+            let m = caps.get(0).unwrap();
+            if !eof && max_match_len.is_some_and(|len| m.len() > len) {
+                self.overlong = true;
+            }
+            out.extend_from_slice(&self.buf[last_match..m.start()]);
+            rule.expand(&caps, crlf, out);
+            last_match = m.end();
+        }
+        let last_end = matches.last_end();
+
+        // Skipped matches still count as matches here
+        let searched_to = last_end.unwrap_or(start);
+        let done = end.max(searched_to).min(self.buf.len());
+        out.extend_from_slice(&self.buf[last_match..done]);
+        // The next window won't see any line breaks after the last match
+        if self.buf[searched_to..done].contains(&b'\n') {
+            start_line(self.rules, &mut self.counts);
+        }
+        self.matched_to_start = last_end == Some(done);
+        let keep_from = done.saturating_sub(CONTEXT);
+        self.buf.drain(..keep_from);
+        self.context = done - keep_from;
+    }
+}
+
+impl Replacer {
+    /// Replaces everything from `reader` into `writer` while only holding on
+    /// to a window of the input at a time
+    ///
+    /// A match can only be replaced once everything it could depend on has
+    /// been read in. When none of the rules can match across a line break
+    /// that's the end of its line, otherwise matches are assumed to be no
+    /// longer than `max_match_len`. Lines that go on for several chunks get
+    /// treated as if matches were no longer than a chunk, to keep from
+    /// holding all of them in memory.
+    pub(crate) fn replace_chunked(
+        &self,
+        reader: impl Read,
+        writer: impl Write,
+    ) -> io::Result<Chunked> {
+        self.replace_chunked_by(reader, writer, CHUNK_SIZE)
+    }
+
+    pub(super) fn replace_chunked_by(
+        &self,
+        mut reader: impl Read,
+        mut writer: impl Write,
+        chunk_size: usize,
+    ) -> io::Result<Chunked> {
+        let crlf = self.progress().crlf;
+        let mut stages: Vec<_> = if self.simultaneous {
+            vec![Stage::new(&self.rules, crlf, chunk_size)]
+        } else {
+            self.rules
+                .chunks(1)
+                .map(|rules| Stage::new(rules, crlf, chunk_size))
+                .collect()
+        };
+
+        let mut chunk = vec![0; chunk_size];
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        loop {
+            let len = match reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let eof = len == 0;
+
+            input.clear();
+            input.extend_from_slice(&chunk[..len]);
+            for stage in &mut stages {
+                output.clear();
+                stage.feed(&input, eof, self.max_match_len, &mut output);
+                std::mem::swap(&mut input, &mut output);
+            }
+            writer.write_all(&input)?;
+
+            if eof {
+                writer.flush()?;
+                return Ok(Chunked {
+                    changed: stages.iter().any(|stage| {
+                        stage.counts.iter().any(|count| count.replaced > 0)
+                    }),
+                    overlong: stages.iter().any(|stage| stage.overlong),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Transcoding text in encodings other than UTF-8, so that it can be matched
+//! against the same way as everything else
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::{Error, Result};
+
+/// The longest byte order mark [`detect`] knows about, UTF-8's
+pub(crate) const MAX_BOM_LEN: usize = 3;
+
+/// Finds out how `content` is encoded, going by its byte order mark and
+/// otherwise by `fallback`, along with how long the byte order mark is
+///
+/// UTF-8 gets matched against as-is, so it gives back `None`.
+pub(crate) fn detect(
+    content: &[u8],
+    fallback: Option<&'static Encoding>,
+) -> Option<(&'static Encoding, usize)> {
+    let (encoding, bom_len) =
+        Encoding::for_bom(content).or(fallback.map(|encoding| (encoding, 0)))?;
+    (encoding != UTF_8).then_some((encoding, bom_len))
+}
+
+/// Decodes `content`, which mustn't start with a byte order mark
+pub(super) fn decode(
+    content: &[u8],
+    encoding: &'static Encoding,
+) -> Result<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(content)
+        .map(|text| text.into_owned())
+        .ok_or(Error::Decode(encoding.name()))
+}
+
+/// Encodes `text` back into `encoding`, without a byte order mark
+pub(super) fn encode(
+    text: &str,
+    encoding: &'static Encoding,
+) -> Result<Vec<u8>> {
+    // `Encoding::encode` only ever produces UTF-8 for UTF-16, same as web
+    // browsers
+    if encoding == UTF_16LE {
+        return Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect());
+    }
+    if encoding == UTF_16BE {
+        return Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect());
+    }
+
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(Error::Encode(encoding.name()));
+    }
+    Ok(bytes.into_owned())
+}
@@ -0,0 +1,84 @@
+//! Carrying a file's metadata over to the new file that takes its place
+
+use std::{
+    fs::{File, Metadata},
+    io,
+    path::Path,
+};
+
+/// Gives `target` the same permissions as `original`, along with the same
+/// owner, group and extended attributes where possible
+///
+/// Extended attributes also hold things like ACLs and SELinux labels. Setting
+/// those or changing the owner can take privileges that the user doesn't
+/// have, so they're carried over on a best-effort basis.
+pub(super) fn copy(
+    original: &File,
+    meta: &Metadata,
+    target: &File,
+) -> io::Result<()> {
+    #[cfg(unix)]
+    copy_owner(meta, target);
+    target.set_permissions(meta.permissions())?;
+    #[cfg(unix)]
+    copy_xattrs(original, target);
+    #[cfg(not(unix))]
+    let _ = original;
+    Ok(())
+}
+
+/// Changing the owner can clear the setuid and setgid bits, so this has to
+/// happen before the permissions get set
+#[cfg(unix)]
+fn copy_owner(meta: &Metadata, target: &File) {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+
+    let fd = target.as_raw_fd();
+    // SAFETY: `fd` stays open for as long as `target` is borrowed
+    unsafe {
+        // Someone who can't give the file away might still be able to change
+        // its group
+        if libc::fchown(fd, meta.uid(), meta.gid()) != 0 {
+            libc::fchown(fd, libc::uid_t::MAX, meta.gid());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn copy_xattrs(original: &File, target: &File) {
+    use xattr::FileExt;
+
+    let Ok(names) = original.list_xattr() else {
+        return;
+    };
+    for name in names {
+        if let Ok(Some(value)) = original.get_xattr(&name) {
+            let _ = target.set_xattr(&name, &value);
+        }
+    }
+}
+
+/// Whether the file has other hard links that would get split off from it
+/// if it were replaced by a new file
+pub(super) fn has_other_links(meta: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        meta.nlink() > 1
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        false
+    }
+}
+
+/// Sets the access and modification times of the file at `path` back to the
+/// ones in `meta`
+pub(super) fn restore_times(path: &Path, meta: &Metadata) -> io::Result<()> {
+    filetime::set_file_times(
+        path,
+        filetime::FileTime::from_last_access_time(meta),
+        filetime::FileTime::from_last_modification_time(meta),
+    )
+}
@@ -1,81 +1,483 @@
-use std::{borrow::Cow, fs, fs::File, io::prelude::*, path::Path};
+use std::{borrow::Cow, fs, fs::File, io::prelude::*, ops::Range, path::Path};
 
-use crate::{utils, Error, Result};
+use crate::{journal::Journal, Error, Result};
 
-use regex::bytes::Regex;
+use regex::bytes::{Captures, Regex};
 
+mod address;
+mod case;
+mod chunked;
+mod encoding;
+mod metadata;
+mod simultaneous;
+mod staged;
+mod syntax;
+mod template;
 #[cfg(test)]
 mod tests;
 mod validate;
 
-pub use validate::{validate_replace, InvalidReplaceCapture};
+use address::AddressState;
+use simultaneous::SimultaneousMatches;
+use template::Template;
 
-pub(crate) struct Replacer {
-    regex: Regex,
-    replace_with: Vec<u8>,
-    is_literal: bool,
+pub(crate) use address::Address;
+pub(crate) use encoding::detect as detect_encoding;
+pub(crate) use staged::Staged;
+pub use validate::{
+    validate_case_escapes, validate_replace, InvalidReplaceCapture,
+};
+
+/// A single find & replace pair along with the options it was built with
+pub(crate) struct Rule {
+    lf: LineEnding,
+    crlf: LineEnding,
+    preserve_case: bool,
     replacements: usize,
+    occurrences: Occurrences,
+    /// Whether a match can never include a line break
+    is_line_local: bool,
 }
 
-impl Replacer {
+/// A rule's pattern and replacement for inputs with one kind of line ending
+struct LineEnding {
+    regex: Regex,
+    replace_with: Template,
+}
+
+/// The options a rule is built with, besides its pattern and replacement
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RuleOptions {
+    /// Whether the pattern and replacement are taken as-is
+    pub(crate) literal: bool,
+    pub(crate) flags: Option<String>,
+    /// The most matches that get replaced, with 0 meaning no limit
+    pub(crate) replacements: usize,
+    pub(crate) occurrences: Occurrences,
+}
+
+/// Which of a rule's matches get replaced, counting from the first one
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Occurrences {
+    /// How many matches get left alone before any are replaced
+    pub(crate) skip: usize,
+    /// Only every this many matches after the skipped ones get replaced
+    pub(crate) every: usize,
+    /// How many matches can be picked out at most
+    pub(crate) take: Option<usize>,
+    /// Whether counting starts over on each line
+    pub(crate) per_line: bool,
+}
+
+/// How far along a single rule is in the input
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Count {
+    /// How many matches were seen since counting last started over
+    seen: usize,
+    replaced: usize,
+}
+
+/// Where a single replacement happened, both in the original text and in the
+/// replaced text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Edit {
+    pub(crate) old: Range<usize>,
+    pub(crate) new: Range<usize>,
+}
+
+/// A single match along with what it gets replaced with
+pub(crate) type Replacement = (Range<usize>, Vec<u8>);
+
+/// A single match that was found by [`Replacer::find_matches`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Match<'r> {
+    pub(crate) range: Range<usize>,
+    /// The pattern's capture groups, leaving out the one for the whole match
+    pub(crate) groups: Vec<Group<'r>>,
+    pub(crate) replacement: Vec<u8>,
+}
+
+/// A capture group of a single match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Group<'r> {
+    pub(crate) name: Option<&'r str>,
+    /// Where the group matched, unless it didn't take part in the match
+    pub(crate) range: Option<Range<usize>>,
+}
+
+/// How modified files get written back
+#[derive(Default)]
+pub(crate) struct WriteOptions {
+    /// The suffix for backups of modified files
+    pub(crate) backup: Option<String>,
+    /// Whether files keep their access and modification times
+    pub(crate) preserve_timestamps: bool,
+    /// Whether either all of the files get replaced or none of them
+    pub(crate) atomic: bool,
+    /// Where the originals of modified files get kept for undoing the run
+    pub(crate) journal: Option<Journal>,
+}
+
+/// How a [`Replacer`] applies its rules
+#[derive(Default)]
+pub(crate) struct ReplacerOptions {
+    /// Whether all of the rules are matched in a single pass, rather than
+    /// one after another
+    pub(crate) simultaneous: bool,
+    pub(crate) max_match_len: Option<usize>,
+    pub(crate) address: Option<Address>,
+    pub(crate) encoding: Option<&'static encoding_rs::Encoding>,
+    /// Whether inputs are never treated as having CRLF line breaks
+    pub(crate) no_crlf: bool,
+}
+
+/// An ordered list of rules that get applied one after another
+pub(crate) struct Replacer {
+    rules: Vec<Rule>,
+    simultaneous: bool,
+    /// The longest a match can be, which lets any file be replaced a chunk
+    /// at a time
+    max_match_len: Option<usize>,
+    address: Option<Address>,
+    /// The encoding of inputs that don't start with a byte order mark, when
+    /// they aren't UTF-8
+    encoding: Option<&'static encoding_rs::Encoding>,
+    /// Whether inputs with CRLF line breaks get treated as such, rather than
+    /// as `\r` at the end of each line
+    detect_line_endings: bool,
+}
+
+/// How many matches each rule has seen and replaced so far
+///
+/// This lets limits on the number of replacements carry over when a single
+/// input gets replaced a piece at a time.
+pub(crate) struct Progress {
+    counts: Vec<Count>,
+    address: Option<AddressState>,
+    /// Whether the input's lines end in CRLF, once that's known
+    crlf: Option<bool>,
+}
+
+impl Rule {
     pub(crate) fn new(
         look_for: String,
         replace_with: String,
-        is_literal: bool,
-        flags: Option<String>,
-        replacements: usize,
+        options: &RuleOptions,
     ) -> Result<Self> {
-        let (look_for, replace_with) = if is_literal {
-            (regex::escape(&look_for), replace_with.into_bytes())
+        let (look_for, replace_with) = if options.literal {
+            (
+                regex::escape(&look_for),
+                Template::Literal(replace_with.into_bytes()),
+            )
         } else {
             validate_replace(&replace_with)?;
+            validate_case_escapes(&replace_with)?;
 
-            (
-                look_for,
-                utils::unescape(&replace_with)
-                    .unwrap_or(replace_with)
-                    .into_bytes(),
-            )
+            (look_for, Template::parse(&replace_with))
         };
 
-        let mut regex = regex::bytes::RegexBuilder::new(&look_for);
-        regex.multi_line(true);
+        let mut pattern = look_for.clone();
+        let mut case_insensitive = false;
+        let mut multi_line = true;
+        let mut dot_matches_new_line = false;
+        let mut preserve_case = false;
 
-        if let Some(flags) = flags {
+        if let Some(flags) = &options.flags {
             flags.chars().for_each(|c| {
                 #[rustfmt::skip]
                 match c {
-                    'c' => { regex.case_insensitive(false); },
-                    'i' => { regex.case_insensitive(true); },
+                    'c' => { case_insensitive = false; },
+                    'i' => { case_insensitive = true; },
                     'm' => {},
-                    'e' => { regex.multi_line(false); },
+                    'p' => {
+                        case_insensitive = true;
+                        preserve_case = true;
+                    },
+                    'e' => { multi_line = false; },
                     's' => {
                         if !flags.contains('m') {
-                            regex.multi_line(false);
+                            multi_line = false;
                         }
-                        regex.dot_matches_new_line(true);
+                        dot_matches_new_line = true;
                     },
                     'w' => {
-                        regex = regex::bytes::RegexBuilder::new(&format!(
-                            "\\b{}\\b",
-                            look_for
-                        ));
+                        // Starts over from the defaults of a fresh builder
+                        pattern = format!("\\b{}\\b", look_for);
+                        case_insensitive = false;
+                        multi_line = false;
+                        dot_matches_new_line = false;
                     },
                     _ => {},
                 };
             });
         };
 
-        Ok(Self {
-            regex: regex.build()?,
+        let mut builder = regex::bytes::RegexBuilder::new(&pattern);
+        builder
+            .case_insensitive(case_insensitive)
+            .multi_line(multi_line)
+            .dot_matches_new_line(dot_matches_new_line);
+        let lf = LineEnding {
+            regex: builder.build()?,
             replace_with,
-            is_literal,
-            replacements,
+        };
+        // Line anchors stop before the `\r` too, and line breaks in the
+        // replacement match the rest of the input
+        let crlf = LineEnding {
+            regex: builder.crlf(true).build()?,
+            replace_with: lf.replace_with.to_crlf(),
+        };
+        let is_line_local = syntax::is_line_local(
+            regex_syntax::ParserBuilder::new()
+                .case_insensitive(case_insensitive)
+                .multi_line(multi_line)
+                .dot_matches_new_line(dot_matches_new_line)
+                .utf8(false),
+            &pattern,
+        );
+
+        Ok(Self {
+            lf,
+            crlf,
+            preserve_case,
+            replacements: options.replacements,
+            occurrences: options.occurrences,
+            is_line_local,
+        })
+    }
+
+    fn line_ending(&self, crlf: bool) -> &LineEnding {
+        if crlf {
+            &self.crlf
+        } else {
+            &self.lf
+        }
+    }
+
+    fn regex(&self, crlf: bool) -> &Regex {
+        &self.line_ending(crlf).regex
+    }
+
+    fn limit_reached(&self, count: &Count) -> bool {
+        self.replacements > 0 && count.replaced >= self.replacements
+    }
+
+    /// Appends the replacement for a single match to `dst`
+    fn expand(&self, caps: &Captures<'_>, crlf: bool, dst: &mut Vec<u8>) {
+        let replace_with = &self.line_ending(crlf).replace_with;
+        if !self.preserve_case {
+            replace_with.expand(caps, dst);
+            return;
+        }
+
+        let mut expanded = Vec::new();
+        replace_with.expand(caps, &mut expanded);
+        // Casing only makes sense for text, so anything else gets left as-is
+        match (
+            std::str::from_utf8(caps.get(0).unwrap().as_bytes()),
+            std::str::from_utf8(&expanded),
+        ) {
+            (Ok(matched), Ok(replacement)) => dst.extend_from_slice(
+                case::preserve_case(matched, replacement).as_bytes(),
+            ),
+            _ => dst.extend_from_slice(&expanded),
+        }
+    }
+}
+
+impl Default for Occurrences {
+    fn default() -> Self {
+        Self {
+            skip: 0,
+            every: 1,
+            take: None,
+            per_line: false,
+        }
+    }
+}
+
+impl Occurrences {
+    /// Whether the `nth` match, counting from 1, gets replaced
+    fn selects(&self, nth: usize) -> bool {
+        // Adding to `skip` could overflow, since it can be as big as `usize`
+        if nth <= self.skip {
+            return false;
+        }
+        let after_skip = nth - self.skip - 1;
+        after_skip % self.every == 0
+            && self
+                .take
+                .map_or(true, |take| after_skip / self.every < take)
+    }
+
+    fn selects_all(&self) -> bool {
+        self.skip == 0 && self.every == 1 && self.take.is_none()
+    }
+}
+
+/// Starts counting matches over for the rules that count them per line
+fn start_line(rules: &[Rule], counts: &mut [Count]) {
+    for (rule, count) in rules.iter().zip(counts) {
+        if rule.occurrences.per_line {
+            count.seen = 0;
+        }
+    }
+}
+
+/// Whether the lines in `content` end in CRLF, going by its first line break
+fn detect_crlf(content: &[u8]) -> Option<bool> {
+    let i = content.iter().position(|&b| b == b'\n')?;
+    Some(i > 0 && content[i - 1] == b'\r')
+}
+
+impl Progress {
+    /// Whether the input's lines end in CRLF, going by `content` if that
+    /// isn't known yet
+    pub(crate) fn crlf(&mut self, content: &[u8]) -> bool {
+        if self.crlf.is_none() {
+            self.crlf = detect_crlf(content);
+        }
+        self.crlf.unwrap_or(false)
+    }
+}
+
+impl Replacer {
+    pub(crate) fn new(rules: Vec<Rule>, options: ReplacerOptions) -> Self {
+        let ReplacerOptions {
+            simultaneous,
+            max_match_len,
+            address,
+            encoding,
+            no_crlf,
+        } = options;
+        Self {
+            rules,
+            simultaneous,
+            max_match_len,
+            address,
+            encoding,
+            detect_line_endings: !no_crlf,
+        }
+    }
+
+    /// Whether `content` has to be transcoded to UTF-8 before the rules can
+    /// be applied to it, going by its start
+    pub(crate) fn is_transcoded(&self, content: &[u8]) -> bool {
+        encoding::detect(content, self.encoding).is_some()
+    }
+
+    /// Gives back `content` as UTF-8 without any byte order mark, for
+    /// showing the replacements in it
+    pub(crate) fn decode<'a>(
+        &self,
+        content: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        Ok(match encoding::detect(content, self.encoding) {
+            Some((encoding, bom_len)) => Cow::Owned(
+                encoding::decode(&content[bom_len..], encoding)?.into_bytes(),
+            ),
+            None => Cow::Borrowed(content),
         })
     }
 
+    /// Replaces in `content` like [`Self::replace`], except that text in any
+    /// other encoding than UTF-8 gets transcoded to UTF-8 and back again,
+    /// keeping its byte order mark
+    pub(crate) fn replace_text<'a>(
+        &'a self,
+        content: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        self.transcode(content, |text| match self.replace(text) {
+            Cow::Owned(replaced) => Some(replaced),
+            Cow::Borrowed(_) => None,
+        })
+    }
+
+    /// Runs `replace` over `content` as UTF-8, transcoding text in any other
+    /// encoding to UTF-8 and back again and keeping its byte order mark
+    ///
+    /// `replace` gives back `None` when it doesn't change anything.
+    fn transcode<'a>(
+        &self,
+        content: &'a [u8],
+        replace: impl FnOnce(&[u8]) -> Option<Vec<u8>>,
+    ) -> Result<Cow<'a, [u8]>> {
+        let Some((encoding, bom_len)) =
+            encoding::detect(content, self.encoding)
+        else {
+            return Ok(
+                replace(content).map_or(Cow::Borrowed(content), Cow::Owned)
+            );
+        };
+
+        let text = encoding::decode(&content[bom_len..], encoding)?;
+        let Some(replaced) = replace(text.as_bytes()) else {
+            return Ok(Cow::Borrowed(content));
+        };
+        // Rules that match single bytes can split up characters
+        let replaced = String::from_utf8(replaced)
+            .map_err(|_| Error::Encode(encoding.name()))?;
+        let mut output = content[..bom_len].to_vec();
+        output.extend(encoding::encode(&replaced, encoding)?);
+        Ok(Cow::Owned(output))
+    }
+
+    /// Whether applying the rules in a single pass gives the same result as
+    /// applying them one after another
+    pub(crate) fn is_single_pass(&self) -> bool {
+        self.simultaneous || self.rules.len() <= 1
+    }
+
+    /// Whether none of the rules can match across a line break, which makes
+    /// replacing line by line the same as replacing everything at once
+    pub(crate) fn is_line_local(&self) -> bool {
+        self.rules.iter().all(|rule| rule.is_line_local)
+    }
+
+    /// Starts keeping track of the replacements for a new input
+    pub(crate) fn progress(&self) -> Progress {
+        Progress {
+            counts: vec![Count::default(); self.rules.len()],
+            address: self.address.as_ref().map(Address::start),
+            crlf: (!self.detect_line_endings).then_some(false),
+        }
+    }
+
+    /// Whether running the rules over `content` would change anything
+    ///
+    /// Checking the original content is enough. A rule that doesn't match
+    /// leaves the text untouched for the rules after it, so if none of them
+    /// match the original then none of them ever will.
     pub(crate) fn has_matches(&self, content: &[u8]) -> bool {
-        self.regex.is_match(content)
+        // Matches that were all skipped over don't change anything
+        if !self.rules.iter().all(|rule| rule.occurrences.selects_all()) {
+            return matches!(self.replace(content), Cow::Owned(_));
+        }
+
+        let mut progress = self.progress();
+        let crlf = progress.crlf(content);
+        self.regions(content, &mut progress)
+            .into_iter()
+            .any(|region| {
+                self.rules.iter().any(|rule| {
+                    rule.regex(crlf).is_match(&content[region.clone()])
+                })
+            })
+    }
+
+    /// The parts of `content` that the rules get applied to
+    fn regions(
+        &self,
+        content: &[u8],
+        progress: &mut Progress,
+    ) -> Vec<Range<usize>> {
+        let crlf = progress.crlf(content);
+        match (&self.address, &mut progress.address) {
+            (Some(address), Some(state)) => {
+                address.regions(content, state, crlf)
+            }
+            _ => std::iter::once(0..content.len()).collect(),
+        }
     }
 
     pub(crate) fn check_not_empty(mut file: File) -> Result<()> {
@@ -88,125 +490,599 @@ impl Replacer {
         &'a self,
         content: &'a [u8],
     ) -> std::borrow::Cow<'a, [u8]> {
-        let regex = &self.regex;
-        let limit = self.replacements;
-        let use_color = false;
-        if self.is_literal {
-            Self::replacen(
-                regex,
-                limit,
+        self.replace_tracked(content, None, &mut self.progress())
+    }
+
+    /// Replaces the next piece of an input that's replaced a piece at a time
+    ///
+    /// Each piece has to end at the end of a line, with or without the line
+    /// break itself.
+    pub(crate) fn replace_part<'a>(
+        &self,
+        content: &'a [u8],
+        progress: &mut Progress,
+    ) -> Cow<'a, [u8]> {
+        self.replace_tracked(content, None, progress)
+    }
+
+    /// Runs all of the rules over the parts of `content` they apply to,
+    /// optionally keeping track of which parts of the output were produced by
+    /// replacements, along with which parts of `content` they took the place
+    /// of
+    fn replace_tracked<'a>(
+        &self,
+        content: &'a [u8],
+        mut highlights: Option<&mut Vec<Edit>>,
+        progress: &mut Progress,
+    ) -> Cow<'a, [u8]> {
+        let crlf = progress.crlf(content);
+        if self.address.is_none() {
+            // Every piece starts on a line of its own
+            start_line(&self.rules, &mut progress.counts);
+            return self.replace_region(
                 content,
-                use_color,
-                regex::bytes::NoExpand(&self.replace_with),
-            )
-        } else {
-            Self::replacen(
-                regex,
-                limit,
+                highlights,
+                &mut progress.counts,
+                crlf,
+            );
+        }
+
+        let mut replaced = Vec::with_capacity(content.len());
+        let mut changed = false;
+        let mut last_end = 0;
+        let mut region_highlights = Vec::new();
+        for region in self.regions(content, progress) {
+            replaced.extend_from_slice(&content[last_end..region.start]);
+            start_line(&self.rules, &mut progress.counts);
+            region_highlights.clear();
+            let new = self.replace_region(
+                &content[region.clone()],
+                highlights.is_some().then_some(&mut region_highlights),
+                &mut progress.counts,
+                crlf,
+            );
+            if let Some(highlights) = highlights.as_deref_mut() {
+                let offset = replaced.len();
+                highlights.extend(region_highlights.iter().map(|edit| Edit {
+                    old: edit.old.start + region.start
+                        ..edit.old.end + region.start,
+                    new: edit.new.start + offset..edit.new.end + offset,
+                }));
+            }
+            changed |= matches!(new, Cow::Owned(_));
+            replaced.extend_from_slice(&new);
+            last_end = region.end;
+        }
+
+        if !changed {
+            return Cow::Borrowed(content);
+        }
+        replaced.extend_from_slice(&content[last_end..]);
+        Cow::Owned(replaced)
+    }
+
+    /// Runs all of the rules over `content`
+    fn replace_region<'a>(
+        &self,
+        content: &'a [u8],
+        mut highlights: Option<&mut Vec<Edit>>,
+        counts: &mut [Count],
+        crlf: bool,
+    ) -> Cow<'a, [u8]> {
+        let track = highlights.is_some();
+        let mut edits = Vec::new();
+        if self.simultaneous {
+            let matches =
+                SimultaneousMatches::new(&self.rules, content, counts, crlf);
+            let replaced = Self::replacen(
                 content,
-                use_color,
-                &*self.replace_with,
-            )
+                matches,
+                crlf,
+                track.then_some(&mut edits),
+            );
+            if let Some(highlights) = highlights {
+                *highlights = edits;
+            }
+            return replaced;
         }
+
+        let mut replaced = Cow::Borrowed(content);
+        for (rule, count) in self.rules.iter().zip(counts) {
+            edits.clear();
+            let matches = SimultaneousMatches::new(
+                std::slice::from_ref(rule),
+                &replaced,
+                std::slice::from_mut(count),
+                crlf,
+            );
+            if let Cow::Owned(new) = Self::replacen(
+                &replaced,
+                matches,
+                crlf,
+                track.then_some(&mut edits),
+            ) {
+                if let Some(highlights) = highlights.as_deref_mut() {
+                    *highlights = remap_highlights(highlights, &edits);
+                }
+                replaced = Cow::Owned(new);
+            }
+        }
+        replaced
     }
 
-    /// A modified form of [`regex::bytes::Regex::replacen`] that supports
-    /// coloring replacements
-    pub(crate) fn replacen<'haystack, R: regex::bytes::Replacer>(
-        regex: &regex::bytes::Regex,
-        limit: usize,
+    /// A modified form of [`regex::bytes::Regex::replacen`] that takes the
+    /// matches along with the rule that produced them and can record where
+    /// each replacement was made
+    pub(crate) fn replacen<'haystack, 'r>(
         haystack: &'haystack [u8],
-        use_color: bool,
-        mut rep: R,
+        matches: impl Iterator<Item = (&'r Rule, Captures<'haystack>)>,
+        crlf: bool,
+        mut edits: Option<&mut Vec<Edit>>,
     ) -> Cow<'haystack, [u8]> {
-        let mut it = regex.captures_iter(haystack).enumerate().peekable();
+        let mut it = matches.peekable();
         if it.peek().is_none() {
             return Cow::Borrowed(haystack);
         }
         let mut new = Vec::with_capacity(haystack.len());
         let mut last_match = 0;
-        for (i, cap) in it {
+        for (rule, cap) in it {
             // unwrap on 0 is OK because captures only reports matches
             let m = cap.get(0).unwrap();
             new.extend_from_slice(&haystack[last_match..m.start()]);
-            if use_color {
-                new.extend_from_slice(
-                    ansi_term::Color::Blue.prefix().to_string().as_bytes(),
-                );
-            }
-            rep.replace_append(&cap, &mut new);
-            if use_color {
-                new.extend_from_slice(
-                    ansi_term::Color::Blue.suffix().to_string().as_bytes(),
-                );
+            let new_start = new.len();
+            rule.expand(&cap, crlf, &mut new);
+            if let Some(edits) = edits.as_deref_mut() {
+                edits.push(Edit {
+                    old: m.range(),
+                    new: new_start..new.len(),
+                });
             }
             last_match = m.end();
-            if limit > 0 && i >= limit - 1 {
-                break;
-            }
         }
         new.extend_from_slice(&haystack[last_match..]);
         Cow::Owned(new)
     }
 
+    /// Replaces every match in `content` in a single pass, giving back where
+    /// each match was along with what it got replaced with
+    ///
+    /// This is for when only the replacements themselves get used, so the
+    /// rules are always applied simultaneously. Applying them one after
+    /// another would match text that was never part of the input.
+    pub(crate) fn replace_matches(
+        &self,
+        content: &[u8],
+        progress: &mut Progress,
+    ) -> Vec<Replacement> {
+        self.find_matches(content, progress)
+            .into_iter()
+            .map(|found| (found.range, found.replacement))
+            .collect()
+    }
+
+    /// Like [`Self::replace_matches`], along with giving back the capture
+    /// groups of each match
+    pub(crate) fn find_matches(
+        &self,
+        content: &[u8],
+        progress: &mut Progress,
+    ) -> Vec<Match<'_>> {
+        let mut found = Vec::new();
+        let mut edits = Vec::new();
+        let mut groups = Vec::new();
+        let crlf = progress.crlf(content);
+        for region in self.regions(content, progress) {
+            start_line(&self.rules, &mut progress.counts);
+            let haystack = &content[region.clone()];
+            let offset = |m: regex::bytes::Match<'_>| {
+                m.start() + region.start..m.end() + region.start
+            };
+            edits.clear();
+            groups.clear();
+            let matches = SimultaneousMatches::new(
+                &self.rules,
+                haystack,
+                &mut progress.counts,
+                crlf,
+            )
+            .inspect(|(rule, caps)| {
+                let names = rule.regex(crlf).capture_names();
+                groups.push(
+                    names
+                        .zip(caps.iter())
+                        .skip(1)
+                        .map(|(name, m)| Group {
+                            name,
+                            range: m.map(offset),
+                        })
+                        .collect::<Vec<_>>(),
+                );
+            });
+            let replaced =
+                Self::replacen(haystack, matches, crlf, Some(&mut edits));
+            found.extend(edits.iter().zip(groups.drain(..)).map(
+                |(edit, groups)| Match {
+                    range: edit.old.start + region.start
+                        ..edit.old.end + region.start,
+                    groups,
+                    replacement: replaced[edit.new.clone()].to_vec(),
+                },
+            ));
+        }
+        found
+    }
+
     pub(crate) fn replace_preview<'a>(
         &self,
         content: &'a [u8],
     ) -> std::borrow::Cow<'a, [u8]> {
-        let regex = &self.regex;
-        let limit = self.replacements;
-        // TODO: refine this condition more
-        let use_color = true;
-        if self.is_literal {
-            Self::replacen(
-                regex,
-                limit,
-                content,
-                use_color,
-                regex::bytes::NoExpand(&self.replace_with),
-            )
+        self.replace_preview_part(content, &mut self.progress())
+    }
+
+    /// Previews the next piece of an input that's replaced a piece at a time
+    pub(crate) fn replace_preview_part<'a>(
+        &self,
+        content: &'a [u8],
+        progress: &mut Progress,
+    ) -> Cow<'a, [u8]> {
+        let mut highlights = Vec::new();
+        let replaced =
+            self.replace_tracked(content, Some(&mut highlights), progress);
+
+        if highlights.is_empty() {
+            return replaced;
+        }
+
+        let mut colored = Vec::with_capacity(replaced.len());
+        let mut last_end = 0;
+        for Edit { new: span, .. } in highlights {
+            colored.extend_from_slice(&replaced[last_end..span.start]);
+            colored.extend_from_slice(
+                ansi_term::Color::Blue.prefix().to_string().as_bytes(),
+            );
+            colored.extend_from_slice(&replaced[span.clone()]);
+            colored.extend_from_slice(
+                ansi_term::Color::Blue.suffix().to_string().as_bytes(),
+            );
+            last_end = span.end;
+        }
+        colored.extend_from_slice(&replaced[last_end..]);
+        Cow::Owned(colored)
+    }
+
+    /// Replaces `content` like [`Self::replace`], along with giving back the
+    /// parts of the replaced text that were produced by replacements and the
+    /// parts of `content` they took the place of
+    ///
+    /// Every match gets a part, even when it's replaced with nothing.
+    pub(crate) fn replace_highlighted<'a>(
+        &self,
+        content: &'a [u8],
+    ) -> (Cow<'a, [u8]>, Vec<Edit>) {
+        let mut highlights = Vec::new();
+        let replaced = self.replace_tracked(
+            content,
+            Some(&mut highlights),
+            &mut self.progress(),
+        );
+        (replaced, highlights)
+    }
+
+    /// Whether a file of `len` bytes gets replaced a chunk at a time
+    ///
+    /// Files too big to comfortably hold in memory get streamed through
+    /// whenever that gives the same result.
+    fn use_chunks(&self, len: u64) -> bool {
+        len > chunked::CHUNK_SIZE as u64
+            && self.address.is_none()
+            && (self.max_match_len.is_some() || self.is_line_local())
+    }
+
+    /// Whether replacing the file at `path` would change its content
+    pub(crate) fn would_change(&self, path: &Path) -> Result<bool> {
+        if Self::check_not_empty(File::open(path)?).is_err() {
+            return Ok(false);
+        }
+
+        let source = File::open(path)?;
+        if self.use_chunks(source.metadata()?.len())
+            && !self.is_file_transcoded(&source)?
+        {
+            let mut compare = Compare {
+                original: std::io::BufReader::new(File::open(path)?),
+                same: true,
+            };
+            let chunked = self.replace_chunked(&source, &mut compare)?;
+            if chunked.overlong {
+                warn_overlong(path);
+            }
+            Ok(!compare.same || compare.original.read(&mut [0])? > 0)
         } else {
-            Self::replacen(
-                regex,
-                limit,
-                content,
-                use_color,
-                &*self.replace_with,
-            )
+            let mmap_source = unsafe { memmap2::Mmap::map(&source)? };
+            Ok(*self.replace_text(&mmap_source)? != *mmap_source)
+        }
+    }
+
+    /// Whether the file read from `source` has to be transcoded, going by
+    /// its byte order mark, leaving `source` back at its start
+    ///
+    /// Only the first few bytes get read, so that deciding on chunking
+    /// doesn't need the whole file mapped.
+    fn is_file_transcoded(&self, mut source: &File) -> Result<bool> {
+        let mut prefix = Vec::with_capacity(encoding::MAX_BOM_LEN);
+        source
+            .take(encoding::MAX_BOM_LEN as u64)
+            .read_to_end(&mut prefix)?;
+        source.rewind()?;
+        Ok(self.is_transcoded(&prefix))
+    }
+
+    /// Replaces the file at `path` in place, first keeping the original
+    /// around if `options` asks for a backup and anything changes
+    pub(crate) fn replace_file(
+        &self,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        match self.stage_file(path)? {
+            Some(staged) => staged.commit(options),
+            None => Ok(()),
         }
     }
 
-    pub(crate) fn replace_file(&self, path: &Path) -> Result<()> {
-        use memmap2::{Mmap, MmapMut};
-        use std::ops::DerefMut;
+    /// Writes out the replaced contents of the file at `path` next to it,
+    /// unless it's empty
+    ///
+    /// The replaced text normally goes to a new file that then takes the
+    /// original's place, carrying over its metadata. A file with other hard
+    /// links gets written over instead, so that it stays the same file for
+    /// all of them.
+    pub(crate) fn stage_file(&self, path: &Path) -> Result<Option<Staged>> {
+        self.stage(path, |source, meta, target| {
+            if self.use_chunks(meta.len())
+                && !self.is_file_transcoded(source)?
+            {
+                let chunked = self
+                    .replace_chunked(source, std::io::BufWriter::new(target))?;
+                if chunked.overlong {
+                    warn_overlong(path);
+                }
+                return Ok(chunked.changed);
+            }
+            let mmap_source = unsafe { memmap2::Mmap::map(source)? };
+            let replaced = self.replace_text(&mmap_source)?;
+            write_mapped(target, &replaced)?;
+            Ok(matches!(replaced, Cow::Owned(_)))
+        })
+    }
+
+    /// Like [`Self::stage_file`], but with `replace` taking the place of the
+    /// rules
+    ///
+    /// `replace` gets the file's text as UTF-8 without any byte order mark,
+    /// and gives back `None` when it doesn't change anything.
+    pub(crate) fn stage_file_with(
+        &self,
+        path: &Path,
+        replace: impl FnOnce(&[u8]) -> Option<Vec<u8>>,
+    ) -> Result<Option<Staged>> {
+        self.stage(path, |source, _, target| {
+            let mmap_source = unsafe { memmap2::Mmap::map(source)? };
+            let replaced = self.transcode(&mmap_source, replace)?;
+            write_mapped(target, &replaced)?;
+            Ok(matches!(replaced, Cow::Owned(_)))
+        })
+    }
 
+    /// Sets up the temporary file for the file at `path`, with `write`
+    /// writing the replaced contents to it and giving back whether they're
+    /// any different
+    fn stage(
+        &self,
+        path: &Path,
+        write: impl FnOnce(&File, &fs::Metadata, &File) -> Result<bool>,
+    ) -> Result<Option<Staged>> {
         if Self::check_not_empty(File::open(path)?).is_err() {
-            return Ok(());
+            return Ok(None);
         }
 
         let source = File::open(path)?;
         let meta = fs::metadata(path)?;
-        let mmap_source = unsafe { Mmap::map(&source)? };
-        let replaced = self.replace(&mmap_source);
+        let in_place = metadata::has_other_links(&meta);
 
         let target = tempfile::NamedTempFile::new_in(
             path.parent()
                 .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?,
         )?;
-        let file = target.as_file();
-        file.set_len(replaced.len() as u64)?;
-        file.set_permissions(meta.permissions())?;
-
-        if !replaced.is_empty() {
-            let mut mmap_target = unsafe { MmapMut::map_mut(file)? };
-            mmap_target.deref_mut().write_all(&replaced)?;
-            mmap_target.flush_async()?;
+        if !in_place {
+            metadata::copy(&source, &meta, target.as_file())?;
         }
 
-        drop(mmap_source);
+        let changed = write(&source, &meta, target.as_file())?;
         drop(source);
 
-        target.persist(fs::canonicalize(path)?)?;
+        let path = fs::canonicalize(path)?;
+        Ok(Some(Staged::new(
+            path,
+            target.into_temp_path(),
+            meta,
+            in_place,
+            changed,
+        )))
+    }
+}
+
+/// Lets the user know that the file at `path` might not have been replaced
+/// in completely, since it was replaced a piece at a time
+fn warn_overlong(path: &Path) {
+    use ansi_term::{Color, Style};
+
+    eprintln!(
+        "{}: {} had matches longer than the longest match that was assumed, \
+         so some matches spanning two pieces of it may have been missed",
+        Style::from(Color::Yellow).bold().paint("warning"),
+        path.display()
+    );
+}
+
+/// Writes `content` out to `file` through a memory map
+fn write_mapped(file: &File, content: &[u8]) -> Result<()> {
+    use std::ops::DerefMut;
+
+    file.set_len(content.len() as u64)?;
+    if !content.is_empty() {
+        let mut mmap = unsafe { memmap2::MmapMut::map_mut(file)? };
+        mmap.deref_mut().write_all(content)?;
+        mmap.flush_async()?;
+    }
+    Ok(())
+}
+
+/// A writer that checks whether everything written to it is the same as
+/// what's read from `original`
+struct Compare<R> {
+    original: R,
+    same: bool,
+}
+
+impl<R: Read> Write for Compare<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.same {
+            let mut expected = vec![0; buf.len()];
+            match self.original.read_exact(&mut expected) {
+                Ok(()) => self.same = expected == buf,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    self.same = false;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 }
+
+/// Keeps the original file at `path` around next to it under a name ending
+/// in `suffix`, with a number added after the suffix if that name is taken
+///
+/// The backup is always a copy. A hard link would share the original's
+/// contents, which get written over in place when a file has other links.
+fn back_up(path: &Path, suffix: &str) -> Result<std::path::PathBuf> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    let mut backup = std::path::PathBuf::from(&name);
+    let mut number = 0;
+    // Only ever creating new files keeps runs going on at the same time from
+    // writing over each other's backups
+    let mut file = loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(file) => break file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                number += 1;
+                let mut numbered = name.clone();
+                numbered.push(format!(".{}", number));
+                backup = numbered.into();
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let mut original = File::open(path)?;
+    std::io::copy(&mut original, &mut file)?;
+    file.set_permissions(original.metadata()?.permissions())?;
+    Ok(backup)
+}
+
+/// Makes the `replacements` in `content`, which have to be in order
+pub(crate) fn apply(content: &[u8], replacements: &[Replacement]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(content.len());
+    let mut last_end = 0;
+    for (matched, replacement) in replacements {
+        replaced.extend_from_slice(&content[last_end..matched.start]);
+        replaced.extend_from_slice(replacement);
+        last_end = matched.end;
+    }
+    replaced.extend_from_slice(&content[last_end..]);
+    replaced
+}
+
+/// Which end of a span a position is
+#[derive(Clone, Copy)]
+enum Bound {
+    Start,
+    End,
+}
+
+/// Carries `pos` in the text before `edits` over to the text after them, or
+/// the other way around when going `backwards`
+///
+/// A position within an edit goes to the start or end of its other side,
+/// depending on which end of a span it is.
+fn map_pos(edits: &[Edit], pos: usize, bound: Bound, backwards: bool) -> usize {
+    let sides = |edit: &Edit| match backwards {
+        false => (edit.old.clone(), edit.new.clone()),
+        true => (edit.new.clone(), edit.old.clone()),
+    };
+    let idx = edits.partition_point(|edit| sides(edit).0.end <= pos);
+    match edits.get(idx).map(sides) {
+        Some((from, to)) if from.start < pos => match bound {
+            Bound::Start => to.start,
+            Bound::End => to.end,
+        },
+        _ => match idx.checked_sub(1).map(|prev| sides(&edits[prev])) {
+            Some((from, to)) => pos - from.end + to.end,
+            None => pos,
+        },
+    }
+}
+
+/// Carries `pos` in the replaced text back to where it was in the original,
+/// going by the `highlights` that [`Replacer::replace_highlighted`] gave back
+///
+/// A position within a replacement goes to the start of what it replaced.
+pub(crate) fn original_pos(highlights: &[Edit], pos: usize) -> usize {
+    map_pos(highlights, pos, Bound::Start, true)
+}
+
+/// Carries the highlighted spans from earlier rules over to the text produced
+/// by a later rule
+///
+/// Spans outside of any edit just get shifted, while a span that a later edit
+/// cuts into gets merged with that edit's replacement. Each span keeps track
+/// of the part of the original text that it took the place of.
+fn remap_highlights(highlights: &[Edit], edits: &[Edit]) -> Vec<Edit> {
+    let mut spans: Vec<_> = highlights
+        .iter()
+        .map(|span| Edit {
+            old: span.old.clone(),
+            new: map_pos(edits, span.new.start, Bound::Start, false)
+                ..map_pos(edits, span.new.end, Bound::End, false),
+        })
+        .chain(edits.iter().map(|edit| Edit {
+            old: map_pos(highlights, edit.old.start, Bound::Start, true)
+                ..map_pos(highlights, edit.old.end, Bound::End, true),
+            new: edit.new.clone(),
+        }))
+        .collect();
+    spans.sort_by_key(|span| (span.new.start, span.new.end));
+
+    let mut merged: Vec<Edit> = Vec::with_capacity(spans.len());
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.new.start < last.new.end => {
+                last.new.end = last.new.end.max(span.new.end);
+                last.old.start = last.old.start.min(span.old.start);
+                last.old.end = last.old.end.max(span.old.end);
+            }
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
@@ -0,0 +1,177 @@
+use regex::bytes::Captures;
+
+use super::{start_line, Count, Rule};
+
+/// The state of the search for a single rule's next match
+enum Next<'h> {
+    /// Needs to be searched for (again) before it can be used
+    Unknown,
+    Found(Captures<'h>),
+    /// The rule won't match anything else in the haystack
+    Done,
+}
+
+/// Matches for several rules found in a single left-to-right scan
+///
+/// At each step the leftmost match out of all of the rules wins, with ties
+/// going to the rule that was given first, the same as an alternation of all
+/// of the patterns would. Searching picks up after the end of the last match,
+/// so text is only ever replaced once and the output of one rule is never
+/// seen by another.
+pub(crate) struct SimultaneousMatches<'r, 'h, 'c> {
+    rules: &'r [Rule],
+    haystack: &'h [u8],
+    next: Vec<Next<'h>>,
+    /// How many matches each rule has seen and replaced
+    counts: &'c mut [Count],
+    /// Where the next match is allowed to start
+    pos: usize,
+    last_end: Option<usize>,
+    /// Matches have to start before this to be reported
+    end: usize,
+    /// Whether the haystack's lines end in CRLF
+    crlf: bool,
+}
+
+impl<'r, 'h, 'c> SimultaneousMatches<'r, 'h, 'c> {
+    pub(crate) fn new(
+        rules: &'r [Rule],
+        haystack: &'h [u8],
+        counts: &'c mut [Count],
+        crlf: bool,
+    ) -> Self {
+        Self {
+            rules,
+            haystack,
+            next: rules.iter().map(|_| Next::Unknown).collect(),
+            counts,
+            pos: 0,
+            last_end: None,
+            end: usize::MAX,
+            crlf,
+        }
+    }
+
+    /// Limits the matches to the ones starting in `start..end`, with
+    /// `last_end` being where the match before them ended
+    ///
+    /// The rest of the haystack is still there for look-around assertions,
+    /// which is what lets a large input be replaced a window at a time.
+    pub(crate) fn within(
+        mut self,
+        start: usize,
+        end: usize,
+        last_end: Option<usize>,
+    ) -> Self {
+        self.pos = start;
+        self.end = end;
+        self.last_end = last_end;
+        self
+    }
+
+    /// Where the last match ended, including any that weren't reported
+    /// because they were skipped
+    pub(crate) fn last_end(&self) -> Option<usize> {
+        self.last_end
+    }
+
+    fn search(&self, rule: &Rule) -> Option<Captures<'h>> {
+        let regex = rule.regex(self.crlf);
+        let caps = regex.captures_at(self.haystack, self.pos)?;
+        let m = caps.get(0).unwrap();
+        // Like the regex crate's iterators, don't report an empty match right
+        // where the previous match ended
+        if m.is_empty() && Some(m.start()) == self.last_end {
+            let next_pos = next_char_boundary(self.haystack, m.start())?;
+            regex.captures_at(self.haystack, next_pos)
+        } else {
+            Some(caps)
+        }
+    }
+
+    /// Whether an earlier found match is no longer usable after the last match
+    fn is_stale(&self, caps: &Captures<'h>) -> bool {
+        let m = caps.get(0).unwrap();
+        m.start() < self.pos
+            || (m.is_empty() && Some(m.start()) == self.last_end)
+    }
+}
+
+impl<'r, 'h, 'c> SimultaneousMatches<'r, 'h, 'c> {
+    /// Finds the leftmost match out of all of the rules and moves past it,
+    /// whether it's going to be replaced or not
+    fn next_match(&mut self) -> Option<(usize, Captures<'h>)> {
+        let mut leftmost: Option<(usize, usize)> = None;
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.limit_reached(&self.counts[i]) {
+                self.next[i] = Next::Done;
+            }
+            let needs_search = match &self.next[i] {
+                Next::Unknown => true,
+                Next::Found(caps) => self.is_stale(caps),
+                Next::Done => false,
+            };
+            if needs_search {
+                self.next[i] = match self.search(rule) {
+                    Some(caps) => Next::Found(caps),
+                    None => Next::Done,
+                };
+            }
+
+            if let Next::Found(caps) = &self.next[i] {
+                let start = caps.get(0).unwrap().start();
+                match leftmost {
+                    Some((_, best)) if best <= start => {}
+                    _ => leftmost = Some((i, start)),
+                }
+            }
+        }
+
+        let (i, start) = leftmost?;
+        if start >= self.end {
+            return None;
+        }
+        let Next::Found(caps) =
+            std::mem::replace(&mut self.next[i], Next::Unknown)
+        else {
+            unreachable!("the leftmost match was just found");
+        };
+        let m = caps.get(0).unwrap();
+        if self.haystack[self.pos..start].contains(&b'\n') {
+            start_line(self.rules, self.counts);
+        }
+        self.pos = m.end();
+        self.last_end = Some(m.end());
+        Some((i, caps))
+    }
+}
+
+impl<'r, 'h, 'c> Iterator for SimultaneousMatches<'r, 'h, 'c> {
+    type Item = (&'r Rule, Captures<'h>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (i, caps) = self.next_match()?;
+            let count = &mut self.counts[i];
+            count.seen += 1;
+            // Matches that aren't selected are left as they are
+            if self.rules[i].occurrences.selects(count.seen) {
+                count.replaced += 1;
+                return Some((&self.rules[i], caps));
+            }
+        }
+    }
+}
+
+/// The position of the start of the character after `pos`, if there is one
+fn next_char_boundary(haystack: &[u8], pos: usize) -> Option<usize> {
+    if pos >= haystack.len() {
+        return None;
+    }
+    let mut next = pos + 1;
+    // Skip over UTF-8 continuation bytes
+    while haystack.get(next).is_some_and(|b| b & 0xC0 == 0x80) {
+        next += 1;
+    }
+    Some(next)
+}
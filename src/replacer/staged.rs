@@ -0,0 +1,177 @@
+use std::{
+    fs::{self, File, Metadata},
+    path::{Path, PathBuf},
+};
+
+use tempfile::{NamedTempFile, TempPath};
+
+use super::{back_up, metadata, WriteOptions};
+use crate::{journal::Entry, Error, Result};
+
+/// A file's replaced contents, written out next to it but not yet put in its
+/// place
+///
+/// Only the path of the replaced contents is held on to, rather than an open
+/// file, so that any number of files can be staged at once without running
+/// out of file descriptors.
+pub(crate) struct Staged {
+    path: PathBuf,
+    target: TempPath,
+    meta: Metadata,
+    /// Whether the original gets written over rather than replaced, since it
+    /// has other hard links
+    in_place: bool,
+    changed: bool,
+}
+
+/// What it takes to put a file back the way it was before its replaced
+/// contents were put in place
+pub(crate) struct Undo {
+    path: PathBuf,
+    /// A hard link to the original where possible, or otherwise a copy of it
+    original: TempPath,
+    in_place: bool,
+    backup: Option<PathBuf>,
+}
+
+impl Staged {
+    pub(super) fn new(
+        path: PathBuf,
+        target: TempPath,
+        meta: Metadata,
+        in_place: bool,
+        changed: bool,
+    ) -> Self {
+        Self {
+            path,
+            target,
+            meta,
+            in_place,
+            changed,
+        }
+    }
+
+    pub(crate) fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Puts the replaced contents in place of the original, first keeping
+    /// the original around if `options` asks for a backup and anything
+    /// changed
+    pub(crate) fn commit(self, options: &WriteOptions) -> Result<()> {
+        let entry = self.keep_original(options)?;
+        if let Some(suffix) = options.backup.as_deref().filter(|_| self.changed)
+        {
+            back_up(&self.path, suffix)?;
+        }
+        self.put_in_place(options)?;
+        if let (Some(journal), Some(entry)) = (&options.journal, entry) {
+            journal.add(entry);
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::commit`], but holds on to the original until the
+    /// [`Undo`] is dropped so that it can still be put back
+    pub(crate) fn commit_undoable(
+        self,
+        options: &WriteOptions,
+    ) -> Result<Undo> {
+        let dir = parent(&self.path)?;
+        let link = (!self.in_place)
+            .then(|| {
+                tempfile::Builder::new()
+                    .make_in(dir, |link| fs::hard_link(&self.path, link))
+                    .ok()
+            })
+            .flatten();
+        let original = match link {
+            Some(link) => link.into_temp_path(),
+            None => {
+                let mut copy = NamedTempFile::new_in(dir)?;
+                std::io::copy(&mut File::open(&self.path)?, &mut copy)?;
+                copy.into_temp_path()
+            }
+        };
+        let entry = self.keep_original(options)?;
+        let backup = match options.backup.as_deref() {
+            Some(suffix) if self.changed => Some(back_up(&self.path, suffix)?),
+            _ => None,
+        };
+
+        let undo = Undo {
+            path: self.path.clone(),
+            original,
+            in_place: self.in_place,
+            backup,
+        };
+        if let Err(e) = self.put_in_place(options) {
+            // Writing over the original can fail partway through
+            let _ = undo.undo();
+            return Err(e);
+        }
+        if let (Some(journal), Some(entry)) = (&options.journal, entry) {
+            journal.add(entry);
+        }
+        Ok(undo)
+    }
+
+    /// Copies the original into the journal, if there is one and anything
+    /// changed
+    fn keep_original(&self, options: &WriteOptions) -> Result<Option<Entry>> {
+        match &options.journal {
+            Some(journal) if self.changed => {
+                journal.keep(&self.path, &self.target).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn put_in_place(self, options: &WriteOptions) -> Result<()> {
+        if !self.in_place {
+            self.target
+                .persist(&self.path)
+                .map_err(|e| Error::File(e.error))?;
+        } else if self.changed {
+            let mut original = fs::OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(&self.path)?;
+            std::io::copy(&mut File::open(&self.target)?, &mut original)?;
+        }
+        if options.preserve_timestamps {
+            metadata::restore_times(&self.path, &self.meta)?;
+        }
+        Ok(())
+    }
+}
+
+impl Undo {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Puts the original back in place, along with removing its backup
+    pub(crate) fn undo(self) -> Result<()> {
+        if self.in_place {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(&self.path)?;
+            std::io::copy(&mut File::open(&self.original)?, &mut file)?;
+        } else {
+            self.original
+                .persist(&self.path)
+                .map_err(|e| Error::File(e.error))?;
+        }
+        if let Some(backup) = self.backup {
+            fs::remove_file(backup)?;
+        }
+        Ok(())
+    }
+}
+
+fn parent(path: &Path) -> Result<&Path> {
+    path.parent()
+        .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))
+}
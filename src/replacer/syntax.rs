@@ -0,0 +1,37 @@
+use regex_syntax::{
+    hir::{Class, Hir, HirKind, Look},
+    ParserBuilder,
+};
+
+/// Whether nothing matched by `pattern` can contain a line break
+///
+/// Patterns that anchor to the start or end of the whole text (`\A`, `\z`, or
+/// `^` and `$` without multi-line mode) don't count either, since those would
+/// start matching at every line.
+pub(super) fn is_line_local(parser: &mut ParserBuilder, pattern: &str) -> bool {
+    match parser.build().parse(pattern) {
+        Ok(hir) => hir_is_line_local(&hir),
+        Err(_) => false,
+    }
+}
+
+fn hir_is_line_local(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Empty => true,
+        HirKind::Literal(lit) => !lit.0.contains(&b'\n'),
+        HirKind::Class(Class::Unicode(class)) => class
+            .ranges()
+            .iter()
+            .all(|range| !(range.start()..=range.end()).contains(&'\n')),
+        HirKind::Class(Class::Bytes(class)) => class
+            .ranges()
+            .iter()
+            .all(|range| !(range.start()..=range.end()).contains(&b'\n')),
+        HirKind::Look(look) => !matches!(look, Look::Start | Look::End),
+        HirKind::Repetition(rep) => hir_is_line_local(&rep.sub),
+        HirKind::Capture(cap) => hir_is_line_local(&cap.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            hirs.iter().all(hir_is_line_local)
+        }
+    }
+}
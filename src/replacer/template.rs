@@ -0,0 +1,160 @@
+use regex::bytes::Captures;
+
+use super::validate::{CaseKind, ReplaceTokenIter, Token};
+use crate::utils;
+
+/// A parsed replacement that knows how to expand itself for a match
+#[derive(Debug)]
+pub(crate) enum Template {
+    /// Inserted as-is
+    Literal(Vec<u8>),
+    /// Text with interpolated captures, interspersed with case conversions
+    Interpolated(Vec<Piece>),
+}
+
+#[derive(Debug)]
+pub(crate) enum Piece {
+    Text(Vec<u8>),
+    Case(CaseKind),
+}
+
+impl Template {
+    /// Parses an already validated replacement string
+    pub(crate) fn parse(replace_with: &str) -> Self {
+        let mut pieces = Vec::new();
+        let push_text = |pieces: &mut Vec<Piece>, text: &str| {
+            if !text.is_empty() {
+                let text = utils::unescape(text).unwrap_or_else(|| text.into());
+                pieces.push(Piece::Text(text.into_bytes()));
+            }
+        };
+
+        let mut last_end = 0;
+        for token in ReplaceTokenIter::new(replace_with) {
+            if let Token::CaseChange(change) = token {
+                push_text(&mut pieces, &replace_with[last_end..change.start()]);
+                pieces.push(Piece::Case(change.kind));
+                last_end = change.end();
+            }
+        }
+        push_text(&mut pieces, &replace_with[last_end..]);
+
+        Self::Interpolated(pieces)
+    }
+
+    /// The same template with every line break turned into a CRLF, leaving
+    /// the ones that already are alone
+    pub(crate) fn to_crlf(&self) -> Self {
+        match self {
+            Self::Literal(bytes) => Self::Literal(to_crlf(bytes)),
+            Self::Interpolated(pieces) => Self::Interpolated(
+                pieces
+                    .iter()
+                    .map(|piece| match piece {
+                        Piece::Text(text) => Piece::Text(to_crlf(text)),
+                        Piece::Case(kind) => Piece::Case(*kind),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Appends the replacement for a single match to `dst`
+    pub(crate) fn expand(&self, caps: &Captures<'_>, dst: &mut Vec<u8>) {
+        let pieces = match self {
+            Self::Literal(bytes) => {
+                dst.extend_from_slice(bytes);
+                return;
+            }
+            Self::Interpolated(pieces) => pieces,
+        };
+
+        // Skip all of the bookkeeping for the common case
+        if let [Piece::Text(text)] = &pieces[..] {
+            caps.expand(text, dst);
+            return;
+        }
+
+        let mut span_case = None;
+        let mut next_case = None;
+        let mut expanded = Vec::new();
+        for piece in pieces {
+            match piece {
+                Piece::Text(text) => {
+                    expanded.clear();
+                    caps.expand(text, &mut expanded);
+                    convert_case(&expanded, span_case, &mut next_case, dst);
+                }
+                Piece::Case(kind) => match kind {
+                    CaseKind::Upper | CaseKind::Lower => {
+                        span_case = Some(*kind)
+                    }
+                    CaseKind::End => span_case = None,
+                    CaseKind::UpperNext | CaseKind::LowerNext => {
+                        next_case = Some(*kind)
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Appends `text` to `dst` with the active case conversions applied
+///
+/// A one-off conversion from `\u` or `\l` is only used up once there's a
+/// character for it to apply to.
+fn convert_case(
+    text: &[u8],
+    span_case: Option<CaseKind>,
+    next_case: &mut Option<CaseKind>,
+    dst: &mut Vec<u8>,
+) {
+    if text.is_empty() || (span_case.is_none() && next_case.is_none()) {
+        dst.extend_from_slice(text);
+        return;
+    }
+
+    let first_case = next_case.take().or(span_case);
+    match std::str::from_utf8(text) {
+        Ok(text) => {
+            let mut converted = String::with_capacity(text.len());
+            for (i, c) in text.chars().enumerate() {
+                match if i == 0 { first_case } else { span_case } {
+                    Some(CaseKind::Upper | CaseKind::UpperNext) => {
+                        converted.extend(c.to_uppercase())
+                    }
+                    Some(CaseKind::Lower | CaseKind::LowerNext) => {
+                        converted.extend(c.to_lowercase())
+                    }
+                    _ => converted.push(c),
+                }
+            }
+            dst.extend_from_slice(converted.as_bytes());
+        }
+        // Only ASCII can be reliably converted without valid UTF-8
+        Err(_) => {
+            for (i, b) in text.iter().enumerate() {
+                dst.push(match if i == 0 { first_case } else { span_case } {
+                    Some(CaseKind::Upper | CaseKind::UpperNext) => {
+                        b.to_ascii_uppercase()
+                    }
+                    Some(CaseKind::Lower | CaseKind::LowerNext) => {
+                        b.to_ascii_lowercase()
+                    }
+                    _ => *b,
+                });
+            }
+        }
+    }
+}
+
+fn to_crlf(text: &[u8]) -> Vec<u8> {
+    let mut crlf = Vec::with_capacity(text.len());
+    for (i, &b) in text.iter().enumerate() {
+        if b == b'\n' && (i == 0 || text[i - 1] != b'\r') {
+            crlf.push(b'\r');
+        }
+        crlf.push(b);
+    }
+    crlf
+}
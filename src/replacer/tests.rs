@@ -14,6 +14,15 @@ proptest! {
         validate::validate_replace(&s).unwrap();
     }
 
+    // Text with `\U…\E` and `\L…\E` spans, or `\u` and `\l` followed by a
+    // letter to convert
+    #[test]
+    fn validate_case_escapes_ok(
+        s in r"([^\$\\]|\\[UL][^\$\\]*\\E|\\[ul][a-zA-Z]){0,10}"
+    ) {
+        validate::validate_case_escapes(&s).unwrap();
+    }
+
     // Force at least one $ followed by a digit and an ident char
     #[test]
     fn validate_err(s in r"[^\$]*?\$[0-9][a-zA-Z_]\PC*") {
@@ -21,6 +30,24 @@ proptest! {
     }
 }
 
+/// A rule with the default options
+fn rule(look_for: &str, replace_with: &str) -> Rule {
+    rule_with(look_for, replace_with, &RuleOptions::default())
+}
+
+fn rule_with(
+    look_for: &str,
+    replace_with: &str,
+    options: &RuleOptions,
+) -> Rule {
+    Rule::new(look_for.into(), replace_with.into(), options).unwrap()
+}
+
+/// A replacer with the default options
+fn default_replacer(rules: Vec<Rule>) -> Replacer {
+    Replacer::new(rules, ReplacerOptions::default())
+}
+
 fn replace(
     look_for: impl Into<String>,
     replace_with: impl Into<String>,
@@ -29,15 +56,17 @@ fn replace(
     src: &'static str,
     target: &'static str,
 ) {
-    const UNLIMITED_REPLACEMENTS: usize = 0;
-    let replacer = Replacer::new(
+    let rule = Rule::new(
         look_for.into(),
         replace_with.into(),
-        literal,
-        flags.map(ToOwned::to_owned),
-        UNLIMITED_REPLACEMENTS,
+        &RuleOptions {
+            literal,
+            flags: flags.map(ToOwned::to_owned),
+            ..RuleOptions::default()
+        },
     )
     .unwrap();
+    let replacer = default_replacer(vec![rule]);
     assert_eq!(
         std::str::from_utf8(&replacer.replace(src.as_bytes())),
         Ok(target)
@@ -79,3 +108,707 @@ fn no_unescape_literal_replacements() {
 fn full_word_replace() {
     replace("abc", "def", false, Some("w"), "abcd abc", "abcd def");
 }
+
+fn replace_all(
+    pairs: &[(&'static str, &'static str)],
+    simultaneous: bool,
+    limit: usize,
+    src: &'static str,
+    target: &'static str,
+) {
+    let rules = pairs
+        .iter()
+        .map(|&(look_for, replace_with)| {
+            let options = RuleOptions {
+                replacements: limit,
+                ..RuleOptions::default()
+            };
+            rule_with(look_for, replace_with, &options)
+        })
+        .collect();
+    let replacer = Replacer::new(
+        rules,
+        ReplacerOptions {
+            simultaneous,
+            ..ReplacerOptions::default()
+        },
+    );
+    assert_eq!(
+        std::str::from_utf8(&replacer.replace(src.as_bytes())),
+        Ok(target)
+    );
+}
+
+#[test]
+fn multiple_rules_apply_in_order() {
+    replace_all(&[("a", "b"), ("b", "c")], false, 0, "ab", "cc");
+    replace_all(&[("b", "c"), ("a", "b")], false, 0, "ab", "bc");
+}
+
+#[test]
+fn multiple_rules_limit_is_per_rule() {
+    replace_all(&[("a", "x"), ("b", "y")], false, 1, "aabb", "xayb");
+}
+
+#[test]
+fn preview_highlights_carry_across_rules() {
+    let rules = vec![rule("a", "bb"), rule("z", "y"), rule("bc", "d")];
+    let replacer = default_replacer(rules);
+    let (blue, reset) = (
+        ansi_term::Color::Blue.prefix().to_string(),
+        ansi_term::Color::Blue.suffix().to_string(),
+    );
+    assert_eq!(
+        std::str::from_utf8(&replacer.replace_preview(b"acz")),
+        Ok(format!("{blue}bd{reset}{blue}y{reset}").as_str())
+    );
+
+    // Each highlight knows what it took the place of in the original
+    let (replaced, highlights) = replacer.replace_highlighted(b"acz");
+    assert_eq!(&*replaced, b"bdy");
+    assert_eq!(
+        highlights,
+        [
+            Edit {
+                old: 0..2,
+                new: 0..2,
+            },
+            Edit {
+                old: 2..3,
+                new: 2..3,
+            },
+        ]
+    );
+    assert_eq!(original_pos(&highlights, 1), 0);
+    assert_eq!(original_pos(&highlights, 2), 2);
+}
+
+#[test]
+fn simultaneous_swap() {
+    let pairs = &[("foo", "bar"), ("bar", "foo")];
+    replace_all(pairs, true, 0, "foo bar foobar", "bar foo barfoo");
+}
+
+#[test]
+fn simultaneous_leftmost_first() {
+    // Earlier rules win ties, otherwise the leftmost match wins
+    replace_all(&[("ab", "1"), ("abc", "2")], true, 0, "abc", "1c");
+    replace_all(&[("b", "1"), ("abc", "2")], true, 0, "abc", "2");
+}
+
+#[test]
+fn simultaneous_limit_is_per_rule() {
+    replace_all(&[("a", "b"), ("b", "a")], true, 1, "abab", "baab");
+}
+
+#[test]
+fn simultaneous_empty_matches() {
+    replace_all(&[("x*", "-")], true, 0, "axxb", "-a-b-");
+    replace_all(&[("b", "B"), ("", "-")], true, 0, "abb", "-aBB");
+}
+
+#[test]
+fn preserve_case_simple() {
+    replace(
+        "user",
+        "account",
+        false,
+        Some("p"),
+        "user User USER",
+        "account Account ACCOUNT",
+    );
+}
+
+#[test]
+fn preserve_case_word_boundaries() {
+    replace(
+        r"user_?name",
+        "account_id",
+        false,
+        Some("p"),
+        "user_name USER_NAME username userName UserName",
+        "account_id ACCOUNT_ID account_id accountId AccountId",
+    );
+    replace(
+        "foo-bar",
+        "newThing",
+        false,
+        Some("p"),
+        "Foo-bar",
+        "New-thing",
+    );
+}
+
+#[test]
+fn preserve_case_with_captures() {
+    replace(
+        r"get_(\w+)",
+        "fetch_$1",
+        false,
+        Some("p"),
+        "get_user GET_USER",
+        "fetch_user FETCH_USER",
+    );
+}
+
+#[test]
+fn preserve_case_without_letters() {
+    replace("123", "abc", false, Some("p"), "123", "abc");
+}
+
+#[test]
+fn case_conversion_escapes() {
+    replace(
+        r"(\w+) (\w+)",
+        r"\U$1\E $2",
+        false,
+        None,
+        "foo bar",
+        "FOO bar",
+    );
+    replace(r"(\w+)", r"\u$1", false, None, "foo", "Foo");
+    replace(r"(\w+)", r"\l\U$1", false, None, "foo", "fOO");
+    replace(r"(\w+)", r"\u\L$1", false, None, "FOO", "Foo");
+    replace("(a)(b)", r"\L$1\E-\Utext$2", false, None, "ab", "a-TEXTB");
+}
+
+#[test]
+fn case_conversion_skips_empty_captures() {
+    replace("(x?)(b)", r"\u$1$2", false, None, "b", "B");
+}
+
+#[test]
+fn case_conversion_keeps_other_escapes() {
+    replace("a", r"\U\tx\\u", false, None, "a", "\tX\\U");
+    replace("a", r"\u0062", false, None, "a", "b");
+}
+
+#[test]
+fn case_conversion_not_in_literal_mode() {
+    replace("a", r"\Ux", true, None, "a", r"\Ux");
+}
+
+#[test]
+fn case_conversion_malformed() {
+    for bad in [r"$1\u", r"\E", r"\U$1\E\E", r"\l\E"] {
+        validate::validate_case_escapes(bad).unwrap_err();
+    }
+}
+
+#[test]
+fn line_local_patterns() {
+    let is_line_local = |look_for: &str, flags: Option<&str>| {
+        let options = RuleOptions {
+            flags: flags.map(ToOwned::to_owned),
+            ..RuleOptions::default()
+        };
+        rule_with(look_for, "", &options).is_line_local
+    };
+
+    for local in [r"foo", r"^\w+$", r"a.b", r"[^\n]+", r"\bx\b"] {
+        assert!(is_line_local(local, None), "{local}");
+    }
+    for spanning in [r"a\nb", r"\s+", r"[^a]", r"\Afoo", r"(?s)a.b"] {
+        assert!(!is_line_local(spanning, None), "{spanning}");
+    }
+    assert!(!is_line_local("a.b", Some("s")));
+    assert!(!is_line_local("^foo", Some("e")));
+}
+
+const CHUNKED_RULES: &[(&str, &str)] = &[
+    ("a", "xy"),
+    ("b{0,2}", "-"),
+    ("^b", "B"),
+    ("a$", "A"),
+    (r"\bab\b", "<$0>"),
+    (r"a\nb", "C"),
+    (r"\n$", "!"),
+    (r"b\s?a", "D"),
+];
+
+proptest! {
+    // No rule above matches more than 3 bytes
+    #[test]
+    fn chunked_matches_whole(
+        src in r"[ab\n ]{0,40}",
+        picks in proptest::collection::vec(0..CHUNKED_RULES.len(), 1..4),
+        simultaneous in any::<bool>(),
+        limit in 0..3usize,
+        skip in 0..3usize,
+        every in 1..3usize,
+        per_line in any::<bool>(),
+        chunk_size in 1..8usize,
+    ) {
+        let options = RuleOptions {
+            replacements: limit,
+            occurrences: Occurrences {
+                skip,
+                every,
+                take: None,
+                per_line,
+            },
+            ..RuleOptions::default()
+        };
+        let rules = || {
+            picks
+                .iter()
+                .map(|&i| {
+                    let (look_for, replace_with) = CHUNKED_RULES[i];
+                    rule_with(look_for, replace_with, &options)
+                })
+                .collect()
+        };
+        let replacer = |max_match_len| {
+            Replacer::new(
+                rules(),
+                ReplacerOptions {
+                    simultaneous,
+                    max_match_len,
+                    ..ReplacerOptions::default()
+                },
+            )
+        };
+        let whole = replacer(None).replace(src.as_bytes()).into_owned();
+        let mut chunked = Vec::new();
+        replacer(Some(3))
+            .replace_chunked_by(src.as_bytes(), &mut chunked, chunk_size)
+            .unwrap();
+        prop_assert_eq!(String::from_utf8(chunked), String::from_utf8(whole));
+    }
+}
+
+#[test]
+fn chunked_by_lines_without_a_max_match_length() {
+    let replacer = default_replacer(vec![rule(r"^a+$", "x")]);
+    let mut chunked = Vec::new();
+    replacer
+        .replace_chunked_by(&b"aaaa\nbaaa\naa"[..], &mut chunked, 3)
+        .unwrap();
+    assert_eq!(chunked, b"x\nbaaa\nx");
+}
+
+#[test]
+fn chunked_long_lines_are_not_held_in_memory() {
+    let replacer = |max_match_len| {
+        Replacer::new(
+            vec![rule("a+", "x")],
+            ReplacerOptions {
+                max_match_len,
+                ..ReplacerOptions::default()
+            },
+        )
+    };
+    let src = "ab".repeat(20);
+
+    // Without a line break in sight, matches are assumed to be no longer than
+    // a chunk
+    let mut chunked = Vec::new();
+    let outcome = replacer(None)
+        .replace_chunked_by(src.as_bytes(), &mut chunked, 3)
+        .unwrap();
+    assert_eq!(chunked, "xb".repeat(20).as_bytes());
+    assert!(outcome.changed);
+    assert!(!outcome.overlong);
+
+    // Matches longer than that get noticed
+    let mut chunked = Vec::new();
+    let outcome = replacer(Some(2))
+        .replace_chunked_by("a".repeat(20).as_bytes(), &mut chunked, 2)
+        .unwrap();
+    assert!(outcome.overlong);
+}
+
+fn replace_within(
+    lines: Option<std::ops::RangeInclusive<usize>>,
+    from: Option<&str>,
+    to: Option<&str>,
+    look_for: &str,
+    src: &str,
+    target: &str,
+) {
+    let rule = rule(look_for, "x");
+    let address = Address::new(lines, from, to).unwrap();
+    let replacer = Replacer::new(
+        vec![rule],
+        ReplacerOptions {
+            address: Some(address),
+            ..ReplacerOptions::default()
+        },
+    );
+    assert_eq!(
+        std::str::from_utf8(&replacer.replace(src.as_bytes())),
+        Ok(target)
+    );
+
+    // Going line by line has to keep track of where the address is at
+    let mut progress = replacer.progress();
+    let by_line: Vec<_> = src
+        .split('\n')
+        .map(|line| {
+            String::from_utf8(
+                replacer.replace_part(line.as_bytes(), &mut progress).into(),
+            )
+            .unwrap()
+        })
+        .collect();
+    assert_eq!(by_line.join("\n"), target);
+}
+
+#[test]
+fn address_line_range() {
+    let src = "a\na\na\na";
+    replace_within(Some(2..=3), None, None, "a", src, "a\nx\nx\na");
+    replace_within(Some(4..=usize::MAX), None, None, "a", src, "a\na\na\nx");
+    replace_within(Some(5..=6), None, None, "a", src, src);
+}
+
+#[test]
+fn address_pattern_range() {
+    let src = "a\nBEGIN a\na\nEND a\na\nBEGIN\na";
+    let (from, to) = (Some("BEGIN"), Some("END"));
+    replace_within(
+        None,
+        from,
+        to,
+        "a",
+        src,
+        "a\nBEGIN x\nx\nEND x\na\nBEGIN\nx",
+    );
+    replace_within(
+        None,
+        None,
+        to,
+        "a",
+        src,
+        "x\nBEGIN x\nx\nEND x\na\nBEGIN\na",
+    );
+    // The line starting a block can't also end it
+    replace_within(
+        None,
+        Some("a"),
+        Some("a"),
+        "a|b",
+        "a\na\nb\na",
+        "x\nx\nb\nx",
+    );
+    replace_within(
+        Some(1..=3),
+        from,
+        to,
+        "a",
+        src,
+        "a\nBEGIN x\nx\nEND a\na\nBEGIN\na",
+    );
+}
+
+#[test]
+fn address_patterns_with_crlf() {
+    let address = |no_crlf| {
+        let address = Address::new(None, Some("BEGIN$"), Some("^END$"));
+        Replacer::new(
+            vec![rule("a", "x")],
+            ReplacerOptions {
+                address: Some(address.unwrap()),
+                no_crlf,
+                ..ReplacerOptions::default()
+            },
+        )
+    };
+    let src = b"a\r\nBEGIN\r\na\r\nEND\r\na\r\n";
+    assert_eq!(
+        &*address(false).replace(src),
+        b"a\r\nBEGIN\r\nx\r\nEND\r\na\r\n"
+    );
+    assert_eq!(&*address(true).replace(src), src);
+}
+
+#[test]
+fn address_never_spans_past_a_region() {
+    let rule = rule(r"b\nb", "x");
+    let address = Address::new(Some(1..=2), None, None).unwrap();
+    let replacer = Replacer::new(
+        vec![rule],
+        ReplacerOptions {
+            address: Some(address),
+            ..ReplacerOptions::default()
+        },
+    );
+    assert_eq!(&*replacer.replace(b"b\nb\nb"), b"x\nb");
+    assert!(replacer.has_matches(b"b\nb\nb"));
+    assert!(!replacer.has_matches(b"a\nb\nb"));
+}
+
+fn replace_occurrences(
+    occurrences: Occurrences,
+    simultaneous: bool,
+    src: &str,
+    target: &str,
+) {
+    let rules = [("a", "x"), ("b", "y")]
+        .into_iter()
+        .map(|(look_for, replace_with)| {
+            let options = RuleOptions {
+                occurrences,
+                ..RuleOptions::default()
+            };
+            rule_with(look_for, replace_with, &options)
+        })
+        .collect();
+    let replacer = Replacer::new(
+        rules,
+        ReplacerOptions {
+            simultaneous,
+            ..ReplacerOptions::default()
+        },
+    );
+    assert_eq!(
+        std::str::from_utf8(&replacer.replace(src.as_bytes())),
+        Ok(target)
+    );
+}
+
+#[test]
+fn occurrence_selection() {
+    let select = |skip, every, take| Occurrences {
+        skip,
+        every,
+        take,
+        per_line: false,
+    };
+    replace_occurrences(select(2, 1, None), false, "aaaa bb", "aaxx bb");
+    replace_occurrences(select(0, 2, None), false, "aaaaa", "xaxax");
+    replace_occurrences(select(1, 1, Some(1)), false, "aaa bbb", "axa byb");
+    replace_occurrences(select(usize::MAX, 1, None), false, "aaa", "aaa");
+    // Each rule counts its own matches
+    replace_occurrences(select(1, 1, Some(1)), true, "abab", "abxy");
+}
+
+#[test]
+fn occurrence_selection_per_line() {
+    let per_line = Occurrences {
+        skip: 1,
+        every: 1,
+        take: Some(1),
+        per_line: true,
+    };
+    replace_occurrences(per_line, false, "aaa\naa\na", "axa\nax\na");
+    replace_occurrences(per_line, true, "aa\nbab", "ax\nbay");
+}
+
+#[test]
+fn occurrence_selection_preview() {
+    let options = RuleOptions {
+        occurrences: Occurrences {
+            skip: 1,
+            ..Occurrences::default()
+        },
+        ..RuleOptions::default()
+    };
+    let rule = rule_with("a", "x", &options);
+    let replacer = default_replacer(vec![rule]);
+    let blue = ansi_term::Color::Blue;
+    assert_eq!(
+        std::str::from_utf8(&replacer.replace_preview(b"aa")),
+        Ok(format!("a{}", blue.paint("x")).as_str())
+    );
+    assert!(!replacer.has_matches(b"a"));
+}
+
+#[test]
+fn compare_writer() {
+    let compare = |original: &[u8], pieces: &[&[u8]]| {
+        let mut compare = Compare {
+            original,
+            same: true,
+        };
+        for piece in pieces {
+            compare.write_all(piece).unwrap();
+        }
+        compare.same && compare.original.is_empty()
+    };
+    assert!(compare(b"abc", &[b"a", b"", b"bc"]));
+    assert!(!compare(b"abc", &[b"a", b"bd"]));
+    assert!(!compare(b"abc", &[b"ab"]));
+    assert!(!compare(b"ab", &[b"a", b"bc"]));
+}
+
+#[test]
+fn transcoding() {
+    let replacer = |fallback| {
+        let rule = rule("é", "ü");
+        Replacer::new(
+            vec![rule],
+            ReplacerOptions {
+                encoding: fallback,
+                ..ReplacerOptions::default()
+            },
+        )
+    };
+    let utf16 = |bom: &[u8], text: &str| {
+        let mut bytes = bom.to_vec();
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    };
+
+    // The byte order mark wins out over the fallback
+    for fallback in [None, Some(encoding_rs::WINDOWS_1252)] {
+        assert_eq!(
+            replacer(fallback)
+                .replace_text(&utf16(b"\xFF\xFE", "café"))
+                .unwrap(),
+            utf16(b"\xFF\xFE", "cafü")
+        );
+    }
+    assert_eq!(
+        &*replacer(Some(encoding_rs::WINDOWS_1252))
+            .replace_text(b"caf\xE9")
+            .unwrap(),
+        b"caf\xFC"
+    );
+    assert_eq!(
+        &*replacer(None).decode(&utf16(b"\xFF\xFE", "café")).unwrap(),
+        "café".as_bytes()
+    );
+
+    // Files too big for a single chunk still go by their byte order mark
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    let text = "café\n".repeat(chunked::CHUNK_SIZE / 8);
+    fs::write(&path, utf16(b"\xFF\xFE", &text)).unwrap();
+    let big = replacer(None);
+    assert!(big.would_change(&path).unwrap());
+    let staged = big.stage_file(&path).unwrap().unwrap();
+    staged.commit(&WriteOptions::default()).unwrap();
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        utf16(b"\xFF\xFE", &text.replace('é', "ü"))
+    );
+
+    // Replacements that can't be encoded fail instead of getting mangled
+    let rule = rule("a", "€");
+    let latin2 = encoding_rs::Encoding::for_label(b"iso-8859-2");
+    let replacer = Replacer::new(
+        vec![rule],
+        ReplacerOptions {
+            encoding: latin2,
+            ..ReplacerOptions::default()
+        },
+    );
+    assert!(replacer.replace_text(b"a").is_err());
+    assert!(matches!(replacer.replace_text(b"b"), Ok(Cow::Borrowed(_))));
+}
+
+#[test]
+fn crlf_line_endings() {
+    let replacer = |look_for: &str, replace_with: &str, no_crlf| {
+        Replacer::new(
+            vec![rule(look_for, replace_with)],
+            ReplacerOptions {
+                no_crlf,
+                ..ReplacerOptions::default()
+            },
+        )
+    };
+
+    let trim = replacer(r"[ \t]*\r?$", "", false);
+    assert_eq!(&*trim.replace(b"a \r\nb\r\n"), b"a\r\nb\r\n");
+    let trim = replacer(r"\s+$", "", false);
+    assert_eq!(&*trim.replace(b"a \r\nb"), b"a\r\nb");
+    // The first line break decides it
+    let split = replacer("-", r"\n", false);
+    assert_eq!(&*split.replace(b"a-b\r\nc-d\n"), b"a\r\nb\r\nc\r\nd\n");
+    assert_eq!(&*split.replace(b"a-b\nc-d\r\n"), b"a\nb\nc\nd\r\n");
+
+    let strip = replacer(r"\r$", "", true);
+    assert_eq!(&*strip.replace(b"a\r\nb\r\n"), b"a\nb\n");
+
+    // Going a chunk at a time goes by the first line break too
+    let mut chunked = Vec::new();
+    split
+        .replace_chunked_by(&b"a-b\r\nc-d\r\n"[..], &mut chunked, 2)
+        .unwrap();
+    assert_eq!(chunked, b"a\r\nb\r\nc\r\nd\r\n");
+}
+
+#[test]
+fn undo_commit() {
+    let rule = rule("a", "b");
+    let replacer = default_replacer(vec![rule]);
+    let options = WriteOptions {
+        backup: Some("~".into()),
+        ..WriteOptions::default()
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    let link = dir.path().join("link");
+    fs::write(&path, "aaa").unwrap();
+    for linked in [false, true] {
+        if linked {
+            fs::hard_link(&path, &link).unwrap();
+        }
+        let staged = replacer.stage_file(&path).unwrap().unwrap();
+        assert!(staged.changed());
+        let undo = staged.commit_undoable(&options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"bbb");
+        undo.undo().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"aaa");
+        if linked {
+            assert_eq!(fs::read(&link).unwrap(), b"aaa");
+        }
+        // Only the file itself is left, along with its link
+        assert_eq!(
+            fs::read_dir(dir.path()).unwrap().count(),
+            1 + usize::from(linked)
+        );
+    }
+}
+
+#[test]
+fn find_matches_with_groups() {
+    let rule = rule(r"(?<key>\w+)=(\d+)?", "$key");
+    let address = Address::new(Some(2..=2), None, None).unwrap();
+    let replacer = Replacer::new(
+        vec![rule],
+        ReplacerOptions {
+            address: Some(address),
+            ..ReplacerOptions::default()
+        },
+    );
+
+    let content = b"a=1\nb=2 c=";
+    let found = replacer.find_matches(content, &mut replacer.progress());
+    assert_eq!(
+        found,
+        [
+            Match {
+                range: 4..7,
+                groups: vec![
+                    Group {
+                        name: Some("key"),
+                        range: Some(4..5),
+                    },
+                    Group {
+                        name: None,
+                        range: Some(6..7),
+                    },
+                ],
+                replacement: b"b".to_vec(),
+            },
+            Match {
+                range: 8..10,
+                groups: vec![
+                    Group {
+                        name: Some("key"),
+                        range: Some(8..9),
+                    },
+                    Group {
+                        name: None,
+                        range: None,
+                    },
+                ],
+                replacement: b"c".to_vec(),
+            },
+        ]
+    );
+}
@@ -6,7 +6,17 @@ use ansi_term::{Color, Style};
 pub struct InvalidReplaceCapture {
     original_replace: String,
     invalid_ident: Span,
-    num_leading_digits: usize,
+    kind: InvalidKind,
+}
+
+#[derive(Debug)]
+enum InvalidKind {
+    /// A numbered capture group that runs into following letters, e.g. `$1a`
+    AmbiguousCapture { num_leading_digits: usize },
+    /// A `\u` or `\l` without anything after it to convert
+    DanglingCaseChange,
+    /// An `\E` without a `\U` or `\L` for it to end
+    UnmatchedCaseEnd,
 }
 
 impl Error for InvalidReplaceCapture {}
@@ -47,7 +57,7 @@ impl fmt::Display for InvalidReplaceCapture {
         let Self {
             original_replace,
             invalid_ident,
-            num_leading_digits,
+            kind,
         } = self;
 
         // Build up the error to show the user
@@ -103,17 +113,49 @@ impl fmt::Display for InvalidReplaceCapture {
         ));
 
         let ident = invalid_ident.slice(original_replace);
-        let (number, the_rest) = ident.split_at(*num_leading_digits);
-        let disambiguous = format!("${{{number}}}{the_rest}");
-        let error_message = format!(
-            "The numbered capture group `{}` in the replacement text is ambiguous.",
-            Style::new().bold().paint(format!("${}", number).to_string())
-        );
-        let hint_message = format!(
-            "{}: Use curly braces to disambiguate it `{}`.",
-            Style::from(Color::Blue).bold().paint("hint"),
-            Style::new().bold().paint(disambiguous)
-        );
+        let bold = Style::new().bold();
+        let hint = Style::from(Color::Blue).bold().paint("hint");
+        let (error_message, hint_message) = match kind {
+            InvalidKind::AmbiguousCapture { num_leading_digits } => {
+                let (number, the_rest) = ident.split_at(*num_leading_digits);
+                let disambiguous = format!("${{{number}}}{the_rest}");
+                (
+                    format!(
+                        "The numbered capture group `{}` in the replacement text is ambiguous.",
+                        bold.paint(format!("${}", number).to_string())
+                    ),
+                    format!(
+                        "{}: Use curly braces to disambiguate it `{}`.",
+                        hint,
+                        bold.paint(disambiguous)
+                    ),
+                )
+            }
+            InvalidKind::DanglingCaseChange => (
+                format!(
+                    "The case conversion `{}` in the replacement text has nothing to convert.",
+                    bold.paint(ident)
+                ),
+                format!(
+                    "{}: Follow it with the text to convert, or escape the backslash `{}`.",
+                    hint,
+                    bold.paint(format!("\\{ident}"))
+                ),
+            ),
+            InvalidKind::UnmatchedCaseEnd => (
+                format!(
+                    "The `{}` in the replacement text doesn't end a case conversion.",
+                    bold.paint(ident)
+                ),
+                format!(
+                    "{}: Start the conversion with `{}` or `{}`, or escape the backslash `{}`.",
+                    hint,
+                    bold.paint("\\U"),
+                    bold.paint("\\L"),
+                    bold.paint(format!("\\{ident}"))
+                ),
+            ),
+        };
 
         writeln!(f, "{}", error_message)?;
         writeln!(f, "{}", hint_message)?;
@@ -123,7 +165,10 @@ impl fmt::Display for InvalidReplaceCapture {
 }
 
 pub fn validate_replace(s: &str) -> Result<(), InvalidReplaceCapture> {
-    for ident in ReplaceCaptureIter::new(s) {
+    for token in ReplaceTokenIter::new(s) {
+        let Token::Capture(ident) = token else {
+            continue;
+        };
         let mut char_it = ident.name.char_indices();
         let (_, c) = char_it.next().unwrap();
         if c.is_ascii_digit() {
@@ -132,7 +177,9 @@ pub fn validate_replace(s: &str) -> Result<(), InvalidReplaceCapture> {
                     return Err(InvalidReplaceCapture {
                         original_replace: s.to_owned(),
                         invalid_ident: ident.span,
-                        num_leading_digits: i,
+                        kind: InvalidKind::AmbiguousCapture {
+                            num_leading_digits: i,
+                        },
                     });
                 }
             }
@@ -142,6 +189,55 @@ pub fn validate_replace(s: &str) -> Result<(), InvalidReplaceCapture> {
     Ok(())
 }
 
+/// Checks that every case conversion escape in `s` has something to apply to
+pub fn validate_case_escapes(s: &str) -> Result<(), InvalidReplaceCapture> {
+    let invalid = |invalid_ident, kind| InvalidReplaceCapture {
+        original_replace: s.to_owned(),
+        invalid_ident,
+        kind,
+    };
+
+    let mut in_case_span = false;
+    let mut changes = ReplaceTokenIter::new(s)
+        .filter_map(|token| match token {
+            Token::CaseChange(change) => Some(change),
+            Token::Capture(_) => None,
+        })
+        .peekable();
+    while let Some(change) = changes.next() {
+        match change.kind {
+            CaseKind::Upper | CaseKind::Lower => in_case_span = true,
+            CaseKind::End if !in_case_span => {
+                return Err(invalid(
+                    change.span,
+                    InvalidKind::UnmatchedCaseEnd,
+                ));
+            }
+            CaseKind::End => in_case_span = false,
+            CaseKind::UpperNext | CaseKind::LowerNext => {
+                // Needs some text right after it, which rules out the end of
+                // the string or something that can't be converted
+                let is_dangling = change.span.end == s.len()
+                    || changes.peek().is_some_and(|next| {
+                        next.span.start == change.span.end
+                            && !matches!(
+                                next.kind,
+                                CaseKind::Upper | CaseKind::Lower
+                            )
+                    });
+                if is_dangling {
+                    return Err(invalid(
+                        change.span,
+                        InvalidKind::DanglingCaseChange,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug)]
 struct Span {
     start: usize,
@@ -187,7 +283,7 @@ impl SpanOpen {
 }
 
 #[derive(Debug)]
-struct Capture<'rep> {
+pub(super) struct Capture<'rep> {
     name: &'rep str,
     span: Span,
 }
@@ -198,26 +294,88 @@ impl<'rep> Capture<'rep> {
     }
 }
 
-/// An iterator over the capture idents in an interpolated replacement string
+/// A case conversion escape in a replacement string
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum CaseKind {
+    /// `\U`: uppercase everything up to the next `\E`
+    Upper,
+    /// `\L`: lowercase everything up to the next `\E`
+    Lower,
+    /// `\E`: ends a `\U` or `\L`
+    End,
+    /// `\u`: uppercase the next character
+    UpperNext,
+    /// `\l`: lowercase the next character
+    LowerNext,
+}
+
+#[derive(Debug)]
+pub(super) struct CaseChange {
+    pub(super) kind: CaseKind,
+    span: Span,
+}
+
+impl CaseChange {
+    pub(super) fn start(&self) -> usize {
+        self.span.start
+    }
+
+    pub(super) fn end(&self) -> usize {
+        self.span.end
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum Token<'rep> {
+    Capture(Capture<'rep>),
+    CaseChange(CaseChange),
+}
+
+/// An iterator over the capture idents and case conversion escapes in an
+/// interpolated replacement string
 ///
-/// This code is adapted from the `regex` crate
+/// The capture handling is adapted from the `regex` crate
 /// <https://docs.rs/regex-automata/latest/src/regex_automata/util/interpolate.rs.html>
 /// (hence the high quality doc comments).
-struct ReplaceCaptureIter<'rep>(CharIndices<'rep>);
+pub(super) struct ReplaceTokenIter<'rep>(CharIndices<'rep>);
 
-impl<'rep> ReplaceCaptureIter<'rep> {
-    fn new(s: &'rep str) -> Self {
+impl<'rep> ReplaceTokenIter<'rep> {
+    pub(super) fn new(s: &'rep str) -> Self {
         Self(s.char_indices())
     }
 }
 
-impl<'rep> Iterator for ReplaceCaptureIter<'rep> {
-    type Item = Capture<'rep>;
+impl<'rep> Iterator for ReplaceTokenIter<'rep> {
+    type Item = Token<'rep>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Continually seek to `$` until we find one that has a capture group
+        // Continually seek to `$` or `\` until we find one that has a capture
+        // group or a case conversion
         loop {
-            let (start, _) = self.0.find(|(_, c)| *c == '$')?;
+            let (start, c) = self.0.find(|(_, c)| *c == '$' || *c == '\\')?;
+
+            if c == '\\' {
+                let rest = self.0.as_str();
+                let kind = match rest.chars().next()? {
+                    // An escaped backslash can't start anything
+                    '\\' => {
+                        self.0.next().unwrap();
+                        continue;
+                    }
+                    'U' => CaseKind::Upper,
+                    'L' => CaseKind::Lower,
+                    'E' => CaseKind::End,
+                    // `\u` followed by four hex digits is a unicode escape
+                    'u' if !is_unicode_escape(&rest[1..]) => {
+                        CaseKind::UpperNext
+                    }
+                    'l' => CaseKind::LowerNext,
+                    _ => continue,
+                };
+                self.0.next().unwrap();
+                let span = Span::new(start, start + 2);
+                return Some(Token::CaseChange(CaseChange { kind, span }));
+            }
 
             let replacement = self.0.as_str();
             let rep = replacement.as_bytes();
@@ -240,12 +398,16 @@ impl<'rep> Iterator for ReplaceCaptureIter<'rep> {
                     remaining_bytes =
                         remaining_bytes.checked_sub(c.len_utf8()).unwrap();
                 }
-                return Some(cap);
+                return Some(Token::Capture(cap));
             }
         }
     }
 }
 
+fn is_unicode_escape(rest: &str) -> bool {
+    rest.len() >= 4 && rest.as_bytes()[..4].iter().all(u8::is_ascii_hexdigit)
+}
+
 /// Parses a possible reference to a capture group name in the given text,
 /// starting at the beginning of `replacement`.
 ///
@@ -303,6 +465,27 @@ mod tests {
 
     use proptest::prelude::*;
 
+    /// An iterator over just the capture idents in an interpolated replacement
+    /// string
+    struct ReplaceCaptureIter<'rep>(ReplaceTokenIter<'rep>);
+
+    impl<'rep> ReplaceCaptureIter<'rep> {
+        fn new(s: &'rep str) -> Self {
+            Self(ReplaceTokenIter::new(s))
+        }
+    }
+
+    impl<'rep> Iterator for ReplaceCaptureIter<'rep> {
+        type Item = Capture<'rep>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.find_map(|token| match token {
+                Token::Capture(cap) => Some(cap),
+                Token::CaseChange(_) => None,
+            })
+        }
+    }
+
     #[test]
     fn literal_dollar_sign() {
         let replace = "$$0";
@@ -0,0 +1,387 @@
+//! Parsing for rules files passed through `--rules`
+//!
+//! Each non-empty line holds a single rule written the same way as sd's own
+//! arguments, so a rule looks like `[OPTIONS] FIND REPLACE_WITH`. Arguments are
+//! separated by whitespace and can be quoted with `'` (taken as-is) or `"`
+//! (where `\"` and `\\` are escaped). Anything after an unquoted `#` at the
+//! start of an argument is a comment.
+//!
+//! The supported options are `-F`/`--fixed-strings`, `-f`/`--flags` and
+//! `-n`/`--max-replacements`. They apply on top of any options passed on the
+//! command line.
+
+use std::{fmt, fs, ops::Range, path::Path, path::PathBuf};
+
+use ansi_term::{Color, Style};
+
+use crate::{
+    replacer::{Rule, RuleOptions},
+    Error, Result,
+};
+
+#[derive(Debug)]
+pub struct InvalidRule {
+    path: PathBuf,
+    line_number: usize,
+    line: String,
+    span: Range<usize>,
+    message: String,
+    /// The underlying error when the rule parsed fine, but couldn't be built
+    cause: Option<String>,
+}
+
+impl std::error::Error for InvalidRule {}
+
+impl fmt::Display for InvalidRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            path,
+            line_number,
+            line,
+            span,
+            message,
+            cause,
+        } = self;
+
+        let error = Style::from(Color::Red).bold();
+        let gutter = Style::from(Color::Blue).bold();
+
+        // Tabs get rendered as a single character so that the arrows line up
+        let render = |s: &str| s.replace('\t', "␉");
+        let before = render(&line[..span.start]);
+        let invalid = render(&line[span.clone()]);
+        let after = render(&line[span.end..]);
+        let column = before.chars().count() + 1;
+
+        let number = line_number.to_string();
+        let padding = " ".repeat(number.len());
+
+        writeln!(f, "{}", message)?;
+        writeln!(
+            f,
+            "{}{} {}:{}:{}",
+            padding,
+            gutter.paint("-->"),
+            path.display(),
+            line_number,
+            column
+        )?;
+        writeln!(
+            f,
+            "{} {}{}{}",
+            gutter.paint(format!("{} |", number)),
+            before,
+            error.paint(&invalid),
+            after
+        )?;
+        write!(
+            f,
+            "{} {}{}",
+            gutter.paint(format!("{} |", padding)),
+            " ".repeat(column - 1),
+            Style::new()
+                .bold()
+                .paint("^".repeat(invalid.chars().count().max(1)))
+        )?;
+        if let Some(cause) = cause {
+            write!(f, "\n{}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the rules file at `path`, with each rule's own options applied on
+/// top of `defaults`
+pub(crate) fn parse_file(
+    path: &Path,
+    defaults: &RuleOptions,
+) -> Result<Vec<Rule>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::RulesFile(path.to_owned(), e))?;
+    parse(path, &content, defaults)
+}
+
+fn parse(
+    path: &Path,
+    content: &str,
+    defaults: &RuleOptions,
+) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let invalid = |span: Range<usize>, message: &str, cause| {
+            Error::InvalidRule(InvalidRule {
+                path: path.to_owned(),
+                line_number: i + 1,
+                line: line.to_owned(),
+                span,
+                message: message.to_owned(),
+                cause,
+            })
+        };
+
+        let args = split_args(line)
+            .map_err(|(span, message)| invalid(span, message, None))?;
+        if args.is_empty() {
+            continue;
+        }
+
+        let parsed = parse_rule(&args, defaults)
+            .map_err(|(span, message)| invalid(span, message, None))?;
+        let rule = Rule::new(
+            parsed.find.value.clone(),
+            parsed.replace_with.value.clone(),
+            &parsed.options,
+        )
+        .map_err(|err| {
+            let (span, message) = match err {
+                Error::InvalidReplaceCapture(_) => (
+                    parsed.replace_with.span.clone(),
+                    "Invalid REPLACE_WITH for rule.",
+                ),
+                _ => (parsed.find.span.clone(), "Invalid FIND for rule."),
+            };
+            invalid(span, message, Some(err.to_string()))
+        })?;
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+/// A single argument of a rule along with where it was in the line
+#[derive(Debug)]
+struct Arg {
+    value: String,
+    span: Range<usize>,
+}
+
+struct ParsedRule<'a> {
+    find: &'a Arg,
+    replace_with: &'a Arg,
+    options: RuleOptions,
+}
+
+type ParseError = (Range<usize>, &'static str);
+
+fn parse_rule<'a>(
+    args: &'a [Arg],
+    defaults: &RuleOptions,
+) -> Result<ParsedRule<'a>, ParseError> {
+    let mut options = defaults.clone();
+    let mut positional = Vec::new();
+
+    let mut it = args.iter();
+    let mut options_done = false;
+    while let Some(arg) = it.next() {
+        let value = arg.value.as_str();
+        if options_done || !value.starts_with('-') || value == "-" {
+            positional.push(arg);
+            continue;
+        }
+
+        // Splits off an option's value, which can either be attached to the
+        // option or be the following argument
+        let mut option_value = |attached: Option<&'a str>| match attached {
+            Some(value) => Ok((value, arg.span.clone())),
+            None => it
+                .next()
+                .map(|next| (next.value.as_str(), next.span.clone()))
+                .ok_or((arg.span.clone(), "Missing value for option.")),
+        };
+
+        match value {
+            "--" => options_done = true,
+            "-F" | "--fixed-strings" => options.literal = true,
+            _ => {
+                let (name, attached) = match value.split_once('=') {
+                    Some((name, v)) if name.starts_with("--") => {
+                        (name, Some(v))
+                    }
+                    _ if !value.starts_with("--")
+                        && value.len() > 2
+                        && value.is_char_boundary(2) =>
+                    {
+                        (&value[..2], Some(&value[2..]))
+                    }
+                    _ => (value, None),
+                };
+                match name {
+                    "-f" | "--flags" => {
+                        let (value, _) = option_value(attached)?;
+                        options
+                            .flags
+                            .get_or_insert_with(String::new)
+                            .push_str(value);
+                    }
+                    "-n" | "--max-replacements" => {
+                        let (value, span) = option_value(attached)?;
+                        options.replacements = value.parse().map_err(|_| {
+                            (span, "Invalid number of replacements.")
+                        })?;
+                    }
+                    _ => return Err((arg.span.clone(), "Unknown option.")),
+                }
+            }
+        }
+    }
+
+    match positional[..] {
+        [find, replace_with] => Ok(ParsedRule {
+            find,
+            replace_with,
+            options,
+        }),
+        [] => {
+            let end = args.last().map_or(0, |arg| arg.span.end);
+            Err((end..end, "Missing FIND and REPLACE_WITH for rule."))
+        }
+        [only] => Err((only.span.clone(), "Missing REPLACE_WITH for rule.")),
+        [_, _, extra, ..] => {
+            Err((extra.span.clone(), "Unexpected extra argument for rule."))
+        }
+    }
+}
+
+/// Splits a line into shell-like arguments, dropping any trailing comment
+fn split_args(line: &str) -> Result<Vec<Arg>, ParseError> {
+    let mut args = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let Some(&(start, first)) = chars.peek() else {
+            break;
+        };
+        if first == '#' {
+            break;
+        }
+
+        let mut value = String::new();
+        let mut end = start;
+        while let Some((i, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            match c {
+                '\'' | '"' => {
+                    let quote = c;
+                    loop {
+                        match chars.next() {
+                            Some((j, c)) if c == quote => {
+                                end = j + c.len_utf8();
+                                break;
+                            }
+                            Some((_, '\\')) if quote == '"' => {
+                                match chars
+                                    .next_if(|&(_, c)| c == '"' || c == '\\')
+                                {
+                                    Some((_, escaped)) => value.push(escaped),
+                                    None => value.push('\\'),
+                                }
+                            }
+                            Some((_, c)) => value.push(c),
+                            None => {
+                                return Err((
+                                    i..line.len(),
+                                    "Unterminated quote in rule.",
+                                ))
+                            }
+                        }
+                    }
+                }
+                c => {
+                    value.push(c);
+                    end = i + c.len_utf8();
+                }
+            }
+        }
+        args.push(Arg {
+            value,
+            span: start..end,
+        });
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(line: &str) -> Vec<String> {
+        split_args(line)
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.value)
+            .collect()
+    }
+
+    #[test]
+    fn split_quoting() {
+        assert_eq!(values("  foo  bar "), ["foo", "bar"]);
+        assert_eq!(
+            values(r#"'a b' "c \"d\" \\ \n""#),
+            ["a b", r#"c "d" \ \n"#]
+        );
+        assert_eq!(values("a'b c'd ''"), ["ab cd", ""]);
+        assert_eq!(values("foo#bar # comment 'unterminated"), ["foo#bar"]);
+        assert!(values("   # only a comment").is_empty());
+    }
+
+    #[test]
+    fn split_spans() {
+        let spans: Vec<_> = split_args("ab  'c d'")
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.span)
+            .collect();
+        assert_eq!(spans, [0..2, 4..9]);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert_eq!(split_args("foo 'bar").unwrap_err().0, 4..8);
+    }
+
+    #[test]
+    fn rule_options() {
+        let args = split_args("-F -f i --max-replacements=2 -- -a b").unwrap();
+        let rule = parse_rule(&args, &RuleOptions::default()).unwrap();
+        assert!(rule.options.literal);
+        assert_eq!(rule.options.flags.as_deref(), Some("i"));
+        assert_eq!(rule.options.replacements, 2);
+        assert_eq!(rule.find.value, "-a");
+        assert_eq!(rule.replace_with.value, "b");
+
+        let defaults = RuleOptions {
+            flags: Some("w".into()),
+            replacements: 3,
+            ..RuleOptions::default()
+        };
+        let args = split_args("-fi a b").unwrap();
+        let rule = parse_rule(&args, &defaults).unwrap();
+        assert_eq!(rule.options.flags.as_deref(), Some("wi"));
+        assert_eq!(rule.options.replacements, 3);
+    }
+
+    #[test]
+    fn rule_errors() {
+        let err = |line| {
+            let args = split_args(line).unwrap();
+            parse_rule(&args, &RuleOptions::default()).err().unwrap()
+        };
+        assert_eq!(err("-x a b"), (0..2, "Unknown option."));
+        assert_eq!(
+            err("-n lots a b"),
+            (3..7, "Invalid number of replacements.")
+        );
+        assert_eq!(err("a"), (0..1, "Missing REPLACE_WITH for rule."));
+        assert_eq!(err("a b c"), (4..5, "Unexpected extra argument for rule."));
+        assert_eq!(err("a -f"), (2..4, "Missing value for option."));
+    }
+
+    #[test]
+    fn parse_skips_blank_lines_and_comments() {
+        let content = "# swap some words\n\nfoo bar\r\n-F '$' dollar\n";
+        let rules = parse(Path::new("rules"), content, &RuleOptions::default())
+            .unwrap();
+        assert_eq!(rules.len(), 2);
+    }
+}
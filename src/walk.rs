@@ -0,0 +1,246 @@
+//! Finding the files to replace in from the paths passed on the command line
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use encoding_rs::Encoding;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    types::{Types, TypesBuilder},
+    WalkBuilder, WalkState,
+};
+
+use crate::{replacer::detect_encoding, Error, Result};
+
+/// Named sets of globs for picking files by their type, like `rust` or `js`
+pub(crate) struct FileTypes<'a> {
+    pub(crate) select: &'a [String],
+    pub(crate) negate: &'a [String],
+    /// Extra definitions like `web:*.{html,css}`
+    pub(crate) add: &'a [String],
+}
+
+impl FileTypes<'_> {
+    /// The built-in file types along with any extra ones
+    fn builder(&self) -> Result<TypesBuilder> {
+        let mut builder = TypesBuilder::new();
+        builder.add_defaults();
+        for def in self.add {
+            builder.add_def(def).map_err(Error::FileType)?;
+        }
+        Ok(builder)
+    }
+
+    fn build(&self) -> Result<Types> {
+        let mut builder = self.builder()?;
+        for name in self.select {
+            builder.select(name);
+        }
+        for name in self.negate {
+            builder.negate(name);
+        }
+        builder.build().map_err(Error::FileType)
+    }
+
+    /// Every file type along with the globs it's made up of
+    pub(crate) fn list(&self) -> Result<Vec<(String, Vec<String>)>> {
+        Ok(self
+            .builder()?
+            .definitions()
+            .into_iter()
+            .map(|def| (def.name().to_owned(), def.globs().to_vec()))
+            .collect())
+    }
+}
+
+/// Which of the candidate files get replaced in
+pub(crate) struct Filter {
+    /// Files have to match one of these, if there are any
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    types: Types,
+    /// The suffix of backups that this run makes, which get left out of
+    /// directories so that they aren't replaced in on the next run
+    backup_suffix: Option<String>,
+}
+
+impl Filter {
+    /// Builds a filter out of globs like `*.rs`, where ones starting with `!`
+    /// exclude the files they match instead, along with file types
+    pub(crate) fn new(
+        globs: &[String],
+        types: &FileTypes,
+        backup_suffix: Option<&str>,
+    ) -> Result<Self> {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut has_include = false;
+        for glob in globs {
+            match glob.strip_prefix('!') {
+                Some(glob) => {
+                    exclude.add(Glob::new(glob)?);
+                }
+                None => {
+                    include.add(Glob::new(glob)?);
+                    has_include = true;
+                }
+            }
+        }
+
+        Ok(Self {
+            include: has_include.then(|| include.build()).transpose()?,
+            exclude: exclude.build()?,
+            types: types.build()?,
+            backup_suffix: backup_suffix.map(ToOwned::to_owned),
+        })
+    }
+
+    /// Whether the file at `path` looks like a backup made with the backup
+    /// suffix, including the numbered ones like `file~.1`
+    fn is_backup(&self, path: &Path) -> bool {
+        let (Some(suffix), Some(name)) =
+            (&self.backup_suffix, path.file_name())
+        else {
+            return false;
+        };
+        let name = name.to_string_lossy();
+        let unnumbered = name
+            .rsplit_once('.')
+            .filter(|(_, number)| {
+                !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+            })
+            .map_or(&*name, |(unnumbered, _)| unnumbered);
+        [&*name, unnumbered].iter().any(|name| {
+            name.len() > suffix.len() && name.ends_with(suffix.as_str())
+        })
+    }
+
+    /// Whether the file at `path` gets replaced in, with `path` being
+    /// relative to where the search started
+    fn allows(&self, path: &Path) -> bool {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        self.include
+            .as_ref()
+            .map_or(true, |include| include.is_match(path))
+            && !self.exclude.is_match(path)
+            && !self.types.matched(path, false).is_ignore()
+    }
+}
+
+/// Expands any directories in `paths` into all of the files within them,
+/// giving back the files that were asked for by name and the ones that were
+/// found in directories, in that order
+///
+/// Directories are walked recursively and in parallel, skipping hidden files,
+/// backups and anything ignored through `.gitignore`, `.ignore` or
+/// `.git/info/exclude`. Everything else is taken as-is, so a file that was
+/// asked for by name is only left out if `filter` doesn't allow it.
+pub(crate) fn files(
+    paths: Vec<PathBuf>,
+    filter: &Filter,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let (dirs, mut files): (Vec<_>, Vec<_>) =
+        paths.into_iter().partition(|path| path.is_dir());
+    files.retain(|path| filter.allows(path));
+
+    let Some((first, rest)) = dirs.split_first() else {
+        return Ok((files, Vec::new()));
+    };
+    let mut builder = WalkBuilder::new(first);
+    for dir in rest {
+        builder.add(dir);
+    }
+    // `.gitignore` files count even where there's no git repository
+    builder.require_git(false);
+
+    let (tx, rx) = mpsc::channel();
+    builder.build_parallel().run(|| {
+        let tx = tx.clone();
+        Box::new(move |entry| {
+            let entry = entry.map(|entry| {
+                let is_file = entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_file());
+                // Globs are matched relative to the directory that was passed
+                let root = entry.path().ancestors().nth(entry.depth());
+                let relative = root
+                    .and_then(|root| entry.path().strip_prefix(root).ok())
+                    .unwrap_or(entry.path());
+                (is_file
+                    && filter.allows(relative)
+                    && !filter.is_backup(relative))
+                .then(|| entry.into_path())
+            });
+            match tx.send(entry) {
+                Ok(()) => WalkState::Continue,
+                Err(_) => WalkState::Quit,
+            }
+        })
+    });
+    drop(tx);
+
+    let mut walked = rx
+        .into_iter()
+        .filter_map(|entry| entry.transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+    // Walking in parallel finds files in whatever order
+    walked.sort();
+
+    let mut seen: HashSet<_> = files.iter().cloned().collect();
+    walked.retain(|path| seen.insert(path.clone()));
+    Ok((files, walked))
+}
+
+/// How much of the start of a file gets looked at to tell whether it's binary
+const BINARY_PREFIX: u64 = 8 * 1024;
+
+/// Splits `paths` into the files that look like text and the ones that look
+/// binary, meaning the start of the file has a NUL character
+///
+/// Files in another encoding than UTF-8, going by their byte order mark or
+/// otherwise `encoding`, are decoded first, since a NUL byte is just part of
+/// a character in UTF-16. Text in legacy encodings like Latin-1 never looks
+/// binary, even though it isn't valid UTF-8. Files that can't be read count
+/// as text, so that the error gets reported once they're replaced in.
+pub(crate) fn split_binary(
+    paths: Vec<PathBuf>,
+    encoding: Option<&'static Encoding>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    use rayon::prelude::*;
+
+    let binary: Vec<bool> = paths
+        .par_iter()
+        .map(|path| {
+            let mut prefix = Vec::new();
+            File::open(path)
+                .and_then(|file| {
+                    file.take(BINARY_PREFIX).read_to_end(&mut prefix)
+                })
+                .is_ok_and(|_| looks_binary(&prefix, encoding))
+        })
+        .collect();
+
+    let (binary, text): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .zip(binary)
+        .partition(|(_, binary)| *binary);
+    (
+        text.into_iter().map(|(path, _)| path).collect(),
+        binary.into_iter().map(|(path, _)| path).collect(),
+    )
+}
+
+fn looks_binary(prefix: &[u8], encoding: Option<&'static Encoding>) -> bool {
+    match detect_encoding(prefix, encoding) {
+        Some((encoding, bom_len)) => encoding
+            .decode_without_bom_handling(&prefix[bom_len..])
+            .0
+            .contains('\0'),
+        None => prefix.contains(&0),
+    }
+}
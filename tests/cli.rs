@@ -82,7 +82,8 @@ mod cli {
             .assert()
             .success()
             .stdout(format!(
-                "{}{}def\n",
+                "{}:{}{}def\n",
+                ansi_term::Color::Green.paint("1"),
                 ansi_term::Color::Blue.prefix(),
                 ansi_term::Color::Blue.suffix()
             ));
@@ -226,7 +227,8 @@ mod cli {
         .assert()
         .success()
         .stdout(format!(
-            "{}\nfoo\nfoo\n",
+            "{}:{}\n",
+            ansi_term::Color::Green.paint("1"),
             ansi_term::Color::Blue.paint("bar")
         ));
 
@@ -250,4 +252,1147 @@ mod cli {
             .success()
             .stdout("bar\nfoo\nfoo");
     }
+
+    #[test]
+    fn multiple_expressions_stdin() {
+        sd().args(["-e", "foo", "bar", "-e", "bar", "baz"])
+            .write_stdin("foo bar")
+            .assert()
+            .success()
+            .stdout("baz baz");
+    }
+
+    #[test]
+    fn multiple_expressions_file() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"abc123def")?;
+        let path = file.into_temp_path();
+
+        sd().args(["-e", "abc", "x", "-e", r"\d+", "", path.to_str().unwrap()])
+            .assert()
+            .success();
+        assert_file(&path, "xdef");
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_expressions_treat_positionals_as_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        std::fs::write(&first, "foo")?;
+        std::fs::write(&second, "foo")?;
+
+        sd().args([
+            "-e",
+            "foo",
+            "bar",
+            first.to_str().unwrap(),
+            second.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+        assert_file(&first, "bar");
+        assert_file(&second, "bar");
+
+        Ok(())
+    }
+
+    #[test]
+    fn simultaneous_expressions() {
+        sd().args(["--simultaneous", "-e", "foo", "bar", "-e", "bar", "foo"])
+            .write_stdin("foo bar")
+            .assert()
+            .success()
+            .stdout("bar foo");
+    }
+
+    #[test]
+    fn rules_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rules = dir.path().join("rules");
+        std::fs::write(
+            &rules,
+            "# cleanup\n\n-F '(a)' b\n-f i -n 1 'B' \"c d\" # only once\n",
+        )?;
+
+        sd().args(["--rules", rules.to_str().unwrap()])
+            .write_stdin("(a) (A) b")
+            .assert()
+            .success()
+            .stdout("c d (A) b");
+
+        // Rules files that can't be read are named in the error
+        let missing = dir.path().join("missing");
+        let not_utf8 = dir.path().join("not_utf8");
+        std::fs::write(&not_utf8, b"a \xFF\n")?;
+        for path in [&missing, &not_utf8] {
+            let assert = sd()
+                .args(["--rules", path.to_str().unwrap()])
+                .write_stdin("a")
+                .assert()
+                .failure();
+            let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+            assert!(stderr.contains(&path.display().to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn bad_rules_helper_plain(rules: &str) -> Result<String> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("rules.sd"), rules)?;
+        let err = sd()
+            .current_dir(dir.path())
+            .args(["--rules", "rules.sd"])
+            .write_stdin("stdin")
+            .unwrap_err();
+        let stderr =
+            String::from_utf8(err.as_output().unwrap().stderr.clone())?;
+        Ok(console::AnsiCodeIterator::new(&stderr)
+            .filter_map(|(s, is_ansi)| (!is_ansi).then_some(s))
+            .collect())
+    }
+
+    #[test]
+    fn rules_file_unterminated_quote() -> Result<()> {
+        let plain_stderr = bad_rules_helper_plain("a b\n\tfoo 'bar baz\n")?;
+        insta::assert_snapshot!(plain_stderr, @r###"
+        error: Unterminated quote in rule.
+         --> rules.sd:2:6
+        2 | ␉foo 'bar baz
+          |      ^^^^^^^^
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rules_file_ambiguous_replace() -> Result<()> {
+        let plain_stderr = bad_rules_helper_plain("(a) $1bad\n")?;
+        insta::assert_snapshot!(plain_stderr, @r###"
+        error: Invalid REPLACE_WITH for rule.
+         --> rules.sd:1:5
+        1 | (a) $1bad
+          |     ^^^^^
+        The numbered capture group `$1` in the replacement text is ambiguous.
+        hint: Use curly braces to disambiguate it `${1}bad`.
+        $1bad
+         ^^^^
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn case_conversion() {
+        sd().args([r"(\w+)@(\w+)", r"\U$1\E at \u$2"])
+            .write_stdin("user@example")
+            .assert()
+            .success()
+            .stdout("USER at Example");
+    }
+
+    #[test]
+    fn case_conversion_dangling() {
+        let plain_stderr = bad_replace_helper_plain(r"before $1\u");
+        insta::assert_snapshot!(plain_stderr, @r###"
+        error: The case conversion `\u` in the replacement text has nothing to convert.
+        hint: Follow it with the text to convert, or escape the backslash `\\u`.
+        before $1\u
+                 ^^
+        "###);
+    }
+
+    #[test]
+    fn case_conversion_unmatched_end() {
+        let plain_stderr = bad_replace_helper_plain(r"\U$1\E\E");
+        insta::assert_snapshot!(plain_stderr, @r###"
+        error: The `\E` in the replacement text doesn't end a case conversion.
+        hint: Start the conversion with `\U` or `\L`, or escape the backslash `\\E`.
+        \U$1\E\E
+              ^^
+        "###);
+    }
+
+    #[test]
+    fn stdin_line_by_line_matches_whole() {
+        sd().args(["x*", "-"])
+            .write_stdin("ab\ncd\n")
+            .assert()
+            .success()
+            .stdout("-a-b-\n-c-d-\n-");
+    }
+
+    #[test]
+    fn stdin_forced_line_by_line() {
+        sd().args(["--line-by-line", r"a\nb", "x"])
+            .write_stdin("a\nb")
+            .assert()
+            .success()
+            .stdout("a\nb");
+        sd().args([r"a\nb", "x"])
+            .write_stdin("a\nb")
+            .assert()
+            .success()
+            .stdout("x");
+    }
+
+    #[test]
+    fn stdin_streams_lines() -> Result<()> {
+        use std::{
+            io::{BufRead, BufReader},
+            process::{Command, Stdio},
+            sync::mpsc,
+            time::Duration,
+        };
+
+        let mut child = Command::new(assert_cmd::cargo::cargo_bin("sd"))
+            .args(["foo", "bar"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+        // The first line should come through while stdin is still open, even
+        // with the start of the next one already there
+        stdin.write_all(b"foo 1\nfo")?;
+        stdin.flush()?;
+        let (tx, rx) = mpsc::channel();
+        let reader = std::thread::spawn(move || {
+            let mut line = String::new();
+            stdout.read_line(&mut line).unwrap();
+            tx.send(line).unwrap();
+            let mut rest = String::new();
+            stdout.read_to_string(&mut rest).unwrap();
+            rest
+        });
+        let line = rx.recv_timeout(Duration::from_secs(30));
+
+        // A line that came in over several reads still gets replaced whole
+        stdin.write_all(b"o 2\n")?;
+        drop(stdin);
+        child.wait()?;
+        let rest = reader.join().unwrap();
+        assert_eq!(line?, "bar 1\n");
+        assert_eq!(rest, "bar 2\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_file_in_chunks() -> Result<()> {
+        // Bigger than a single chunk, with a match across every boundary
+        let content = "foo\nbar\n".repeat(1024 * 1024);
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(content.as_bytes())?;
+        let path = file.into_temp_path();
+
+        sd().args(["--max-match-length", "16", "-n", "1048575"])
+            .args([r"o\nb", "0-B", path.to_str().unwrap()])
+            .assert()
+            .success();
+        let replaced = std::fs::read_to_string(&path)?;
+        assert_eq!(replaced.len(), content.len());
+        assert_eq!(replaced.matches("fo0-Bar\n").count(), 1024 * 1024 - 1);
+        assert!(replaced.ends_with("foo\nbar\n"));
+
+        sd().args(["^fo", "Fo", path.to_str().unwrap()])
+            .assert()
+            .success();
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            replaced.replace("fo", "Fo")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_matching_stdin() {
+        sd().args(["-o", r"(\w+)@(\w+)", "$2"])
+            .write_stdin("a@b c\nd@e f@g\nnothing")
+            .assert()
+            .success()
+            .stdout("b\ne\ng\n");
+        sd().args(["-o", r"\d+"])
+            .write_stdin("1 and 22, 333")
+            .assert()
+            .success()
+            .stdout("1\n22\n333\n");
+        sd().args(["-o", "-F", "."])
+            .write_stdin("a.b.")
+            .assert()
+            .success()
+            .stdout(".\n.\n");
+        // Empty matches aren't shown
+        sd().args(["-o", "x*"])
+            .write_stdin("axxb\nc")
+            .assert()
+            .success()
+            .stdout("xx\n");
+    }
+
+    #[test]
+    fn only_matching_files_with_prefixes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        std::fs::write(&first, "x1\n\nx2 x3")?;
+        std::fs::write(&second, "none\nx4")?;
+
+        sd().args(["-o", "--with-filename", "--line-number"])
+            .args(["-e", r"x(\d)", "<$1>"])
+            .args([&first, &second])
+            .assert()
+            .success()
+            .stdout(format!(
+                "{0}:1:<1>\n{0}:3:<2>\n{0}:3:<3>\n{1}:2:<4>\n",
+                first.display(),
+                second.display()
+            ));
+        // Files are only read
+        assert_file(&first, "x1\n\nx2 x3");
+
+        // Files that can't be read don't keep the others from being searched
+        let missing = dir.path().join("missing");
+        let assert = sd()
+            .args(["-o", r"x\d", "$0"])
+            .args([&missing, &second])
+            .assert()
+            .failure()
+            .stdout("x4\n");
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+        assert!(stderr.contains(&missing.display().to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn line_and_pattern_ranges() -> Result<()> {
+        sd().args(["--lines", "2,3", "a", "x"])
+            .write_stdin("a\na\na\na\n")
+            .assert()
+            .success()
+            .stdout("a\nx\nx\na\n");
+        sd().args(["--from", "^BEGIN$", "--to", "^END$", "a", "x"])
+            .write_stdin("a\nBEGIN\na\nEND\na\n")
+            .assert()
+            .success()
+            .stdout("a\nBEGIN\nx\nEND\na\n");
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"a\na\na")?;
+        let path = file.into_temp_path();
+        sd().args(["--lines", ",2", "-f", "s", r"a\s*", "x"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&path, "xx\na");
+
+        let assert = sd().args(["--lines", "3,2", "a", "x"]).assert().failure();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+        assert!(stderr.contains("the range ends before it starts"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn occurrence_selection() {
+        sd().args(["--nth", "2", "a", "x"])
+            .write_stdin("aaa\naaa")
+            .assert()
+            .success()
+            .stdout("axa\naaa");
+        sd().args(["--nth", "2", "--per-line", "a", "x"])
+            .write_stdin("aaa\naaa")
+            .assert()
+            .success()
+            .stdout("axa\naxa");
+        sd().args(["--skip", "1", "--every", "2", "a", "x"])
+            .write_stdin("aaaaaa")
+            .assert()
+            .success()
+            .stdout("axaxax");
+        sd().args(["--nth", "2", "--skip", "1", "a", "x"])
+            .write_stdin("aa")
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn recursive_directories() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for path in ["a", "sub/b", "sub/deep/c", "ignored", ".hidden/d", "e"] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, "foo")?;
+        }
+        // Outside of a git repository, so `.gitignore` has to work without
+        // one
+        std::fs::write(root.join(".gitignore"), "ignored\n")?;
+        std::fs::write(root.join(".ignore"), "e\n")?;
+
+        sd().args(["foo", "bar"]).arg(root).assert().success();
+        for replaced in ["a", "sub/b", "sub/deep/c"] {
+            assert_file(&root.join(replaced), "bar");
+        }
+        for skipped in ["ignored", ".hidden/d", "e"] {
+            assert_file(&root.join(skipped), "foo");
+        }
+        assert_file(&root.join(".gitignore"), "ignored\n");
+
+        // Files passed by name are always replaced
+        sd().args(["foo", "bar"])
+            .arg(root.join("ignored"))
+            .arg(root.join(".hidden"))
+            .assert()
+            .success();
+        assert_file(&root.join("ignored"), "bar");
+        assert_file(&root.join(".hidden/d"), "bar");
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_filters() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for path in ["a.rs", "b.txt", "src/c.rs", "target/d.rs"] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, "foo")?;
+        }
+
+        sd().args(["-g", "*.rs", "--glob", "!target/**", "foo", "bar"])
+            .arg(root)
+            .assert()
+            .success();
+        assert_file(&root.join("a.rs"), "bar");
+        assert_file(&root.join("src/c.rs"), "bar");
+        assert_file(&root.join("b.txt"), "foo");
+        assert_file(&root.join("target/d.rs"), "foo");
+
+        // Files passed directly get filtered too
+        sd().args(["-g", "!*.txt", "bar", "baz"])
+            .args([root.join("a.rs"), root.join("b.txt")])
+            .assert()
+            .success();
+        assert_file(&root.join("a.rs"), "baz");
+        assert_file(&root.join("b.txt"), "foo");
+
+        sd().args(["-g", "a[", "foo", "bar"])
+            .arg(root)
+            .assert()
+            .failure();
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_type_filters() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for path in ["a.rs", "b.py", "c.md", "d.foo"] {
+            std::fs::write(root.join(path), "foo")?;
+        }
+
+        sd().args(["-t", "rust", "--type", "foo", "--type-add", "foo:*.foo"])
+            .args(["foo", "bar"])
+            .arg(root)
+            .assert()
+            .success();
+        assert_file(&root.join("a.rs"), "bar");
+        assert_file(&root.join("d.foo"), "bar");
+        assert_file(&root.join("b.py"), "foo");
+
+        sd().args(["-T", "markdown", "foo", "baz"])
+            .arg(root)
+            .assert()
+            .success();
+        assert_file(&root.join("b.py"), "baz");
+        assert_file(&root.join("c.md"), "foo");
+
+        sd().args(["-t", "nope", "foo", "bar"])
+            .arg(root)
+            .assert()
+            .failure();
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_type_list() {
+        let assert = sd()
+            .args(["--type-list", "--type-add", "foo:*.foo"])
+            .assert()
+            .success();
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+        assert!(stdout.lines().any(|line| line == "foo: *.foo"));
+        assert!(stdout.lines().any(|line| line == "rust: *.rs"));
+    }
+
+    #[test]
+    fn backups() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        let unchanged = dir.path().join("unchanged");
+        std::fs::write(&path, "foo")?;
+        std::fs::write(&unchanged, "baz")?;
+
+        sd().args(["--backup", "foo", "bar"])
+            .args([&path, &unchanged])
+            .assert()
+            .success();
+        assert_file(&path, "bar");
+        assert_file(&dir.path().join("file~"), "foo");
+        assert!(!dir.path().join("unchanged~").exists());
+
+        // Existing backups are never written over
+        sd().args(["--backup", "bar", "qux"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&dir.path().join("file~"), "foo");
+        assert_file(&dir.path().join("file~.1"), "bar");
+
+        sd().args(["--backup=.bak", "qux", "quux"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&path, "quux");
+        assert_file(&dir.path().join("file.bak"), "qux");
+
+        // Backups in a directory aren't replaced in by the next run
+        sd().args(["--backup", "quux", "x", "--"])
+            .arg(dir.path())
+            .assert()
+            .success();
+        sd().args(["--backup", "foo", "y", "--"])
+            .arg(dir.path())
+            .assert()
+            .success();
+        assert_file(&dir.path().join("file~"), "foo");
+        assert_file(&dir.path().join("file~.1"), "bar");
+        assert_file(&dir.path().join("file~.2"), "quux");
+        assert!(!dir.path().join("file~~").exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_stay_linked() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        let link = dir.path().join("link");
+        std::fs::write(&path, "foo")?;
+        std::fs::hard_link(&path, &link)?;
+        let inode = std::fs::metadata(&path)?.ino();
+
+        sd().args(["--backup", "foo", "bar"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&link, "bar");
+        assert_eq!(std::fs::metadata(&path)?.ino(), inode);
+        assert_eq!(std::fs::metadata(&link)?.nlink(), 2);
+        assert_file(&dir.path().join("file~"), "foo");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extended_attributes_are_kept() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        std::fs::write(&path, "foo")?;
+        if xattr::set(&path, "user.sd.test", b"value").is_err() {
+            // The filesystem doesn't support user attributes
+            return Ok(());
+        }
+
+        sd().args(["foo", "bar"]).arg(&path).assert().success();
+        assert_file(&path, "bar");
+        assert_eq!(
+            xattr::get(&path, "user.sd.test")?.as_deref(),
+            Some(&b"value"[..])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn preserve_timestamps() -> Result<()> {
+        use filetime::FileTime;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        std::fs::write(&path, "foo")?;
+        let then = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&path, then)?;
+
+        sd().args(["--preserve-timestamps", "foo", "bar"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&path, "bar");
+        let meta = std::fs::metadata(&path)?;
+        assert_eq!(FileTime::from_last_modification_time(&meta), then);
+
+        sd().args(["bar", "baz"]).arg(&path).assert().success();
+        let meta = std::fs::metadata(&path)?;
+        assert_ne!(FileTime::from_last_modification_time(&meta), then);
+
+        Ok(())
+    }
+
+    #[test]
+    fn binary_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let text = dir.path().join("text");
+        let binary = dir.path().join("binary");
+        let latin1 = dir.path().join("latin1");
+        std::fs::write(&text, "foo")?;
+        std::fs::write(&binary, b"foo\0")?;
+        std::fs::write(&latin1, b"caf\xe9 foo")?;
+
+        let assert = sd().args(["foo", "bar"]).arg(dir.path()).assert();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+        assert!(stderr.contains(&binary.display().to_string()));
+        assert!(!stderr.contains(&latin1.display().to_string()));
+        assert!(!stderr.contains(&text.display().to_string()));
+        assert.success();
+        assert_file(&text, "bar");
+        assert_eq!(std::fs::read(&binary)?, b"foo\0");
+        assert_eq!(std::fs::read(&latin1)?, b"caf\xe9 bar");
+
+        sd().args(["--binary", "foo", "bar"])
+            .arg(dir.path())
+            .assert()
+            .success()
+            .stderr("");
+        assert_eq!(std::fs::read(&binary)?, b"bar\0");
+
+        // Files passed by name are never skipped
+        sd().args(["bar", "baz"])
+            .arg(&binary)
+            .assert()
+            .success()
+            .stderr("");
+        assert_eq!(std::fs::read(&binary)?, b"baz\0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encodings() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let utf16 = dir.path().join("utf16");
+        let latin1 = dir.path().join("latin1");
+        let encode = |text: &str| {
+            let mut bytes = b"\xFF\xFE".to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            bytes
+        };
+        std::fs::write(&utf16, encode("café\r\n"))?;
+        std::fs::write(&latin1, b"caf\xE9\n")?;
+
+        // UTF-16 is full of NUL bytes, but it's still text
+        sd().args(["--encoding", "latin1", "é", "ü"])
+            .arg(dir.path())
+            .assert()
+            .success()
+            .stderr("");
+        assert_eq!(std::fs::read(&utf16)?, encode("cafü\r\n"));
+        assert_eq!(std::fs::read(&latin1)?, b"caf\xFC\n");
+
+        sd().args(["-o", "caf.", "$0"])
+            .arg(&utf16)
+            .assert()
+            .success()
+            .stdout("cafü\n");
+
+        sd().args(["--encoding", "klingon", "a", "b"])
+            .arg(&latin1)
+            .assert()
+            .failure();
+
+        Ok(())
+    }
+
+    #[test]
+    fn crlf_line_endings() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "foo \r\nbar\r\n")?;
+
+        sd().args([r"\s+$", ""]).arg(file.path()).assert().success();
+        assert_file(file.path(), "foo\r\nbar");
+
+        sd().args(["--line-by-line", "o+$", "o\n"])
+            .write_stdin("foo \r\nfoo\r\n")
+            .assert()
+            .success()
+            .stdout("foo \r\nfo\r\n\r\n");
+
+        sd().args(["--no-crlf", r"\r$", ""])
+            .write_stdin("foo\r\nbar\r\n")
+            .assert()
+            .success()
+            .stdout("foo\nbar\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn atomic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        let missing = dir.path().join("missing");
+        std::fs::write(&first, "foo")?;
+        std::fs::write(&second, "foo")?;
+
+        let assert = sd()
+            .args(["--atomic", "foo", "bar"])
+            .args([&first, &missing, &second])
+            .assert()
+            .failure();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+        assert!(stderr.contains("no files were changed"));
+        assert_file(&first, "foo");
+        assert_file(&second, "foo");
+
+        sd().args(["--atomic", "foo", "bar"])
+            .args([&first, &second])
+            .assert()
+            .success();
+        assert_file(&first, "bar");
+        assert_file(&second, "bar");
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo_journal() -> Result<()> {
+        let journal = tempfile::tempdir()?;
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        std::fs::write(&first, "foo")?;
+        std::fs::write(&second, "foo")?;
+        let sd = || {
+            let mut sd = sd();
+            sd.env("SD_JOURNAL_DIR", journal.path());
+            sd
+        };
+
+        sd().arg("--undo").assert().failure();
+
+        // Runs that don't change anything leave nothing behind
+        sd().args(["--journal", "--check", "foo", "bar"])
+            .arg(&first)
+            .assert()
+            .code(2);
+        assert_eq!(std::fs::read_dir(journal.path())?.count(), 0);
+
+        sd().args(["--journal", "foo", "bar"])
+            .args([&first, &second])
+            .assert()
+            .success();
+        assert_file(&first, "bar");
+        sd().arg("--undo").assert().success();
+        assert_file(&first, "foo");
+        assert_file(&second, "foo");
+        // The last run can only be undone once
+        sd().arg("--undo").assert().failure();
+
+        // Nothing gets undone once anything changed since
+        sd().args(["--journal", "foo", "bar"])
+            .args([&first, &second])
+            .assert()
+            .success();
+        std::fs::write(&second, "baz")?;
+        sd().arg("--undo").assert().failure();
+        assert_file(&first, "bar");
+        assert_file(&second, "baz");
+
+        Ok(())
+    }
+
+    // macOS doesn't allow file names that aren't valid UTF-8
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn undo_journal_non_utf8_path() -> Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let journal = tempfile::tempdir()?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::write(&path, "foo")?;
+
+        sd().env("SD_JOURNAL_DIR", journal.path())
+            .args(["--journal", "foo", "bar"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_file(&path, "bar");
+        sd().env("SD_JOURNAL_DIR", journal.path())
+            .arg("--undo")
+            .assert()
+            .success();
+        assert_file(&path, "foo");
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff_mode() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "a\nb\nc\nd\ne\nf\ng\n")?;
+        let name = file.path().display();
+
+        sd().args(["--diff", "-U", "1", "b|f", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(format!(
+                "--- {name}\n+++ {name}\n\
+                 @@ -1,3 +1,3 @@\n a\n-b\n+x\n c\n\
+                 @@ -5,3 +5,3 @@\n e\n-f\n+x\n g\n"
+            ));
+        assert_file(file.path(), "a\nb\nc\nd\ne\nf\ng\n");
+
+        sd().args(["--diff", "z", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout("");
+
+        // Files that can't be read don't keep the others from being diffed
+        let missing = file.path().with_extension("missing");
+        let assert = sd()
+            .args(["--diff", "-U", "0", "b", "x"])
+            .arg(&missing)
+            .arg(file.path())
+            .assert()
+            .failure()
+            .stdout(format!("--- {name}\n+++ {name}\n@@ -2 +2 @@\n-b\n+x\n"));
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+        assert!(stderr.contains(&missing.display().to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_context() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "a\nb\nc\nd\ne\nf\ng\nh\n")?;
+        let n = |n: usize| ansi_term::Color::Green.paint(n.to_string());
+        let x = ansi_term::Color::Blue.paint("x");
+
+        sd().args(["-p", "b|g", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(format!("{}:{x}\n{}:{x}\n", n(2), n(7)));
+
+        sd().args(["-p", "-C", "1", "-A", "0", "b|g", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(format!(
+                "{}-a\n{}:{x}\n--\n{}-f\n{}:{x}\n",
+                n(1),
+                n(2),
+                n(6),
+                n(7)
+            ));
+
+        sd().args(["-p", "-A", "2", "b|g", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(format!(
+                "{}:{x}\n{}-c\n{}-d\n--\n{}:{x}\n{}-h\n",
+                n(2),
+                n(3),
+                n(4),
+                n(7),
+                n(8)
+            ));
+
+        // Line numbers are those of the original, even once replacements
+        // take out line breaks
+        sd().args(["-p", "-A", "1", r"b\nc|g", "x"])
+            .arg(file.path())
+            .assert()
+            .success()
+            .stdout(format!(
+                "{}:{x}\n{}-d\n--\n{}:{x}\n{}-h\n",
+                n(2),
+                n(4),
+                n(7),
+                n(8)
+            ));
+        assert_file(file.path(), "a\nb\nc\nd\ne\nf\ng\nh\n");
+
+        // STDIN gets previewed whole, so context can't be picked for it
+        for flag in ["-C", "-A", "-B"] {
+            sd().args(["-p", flag, "1", "a", "x"])
+                .write_stdin("a\nb\n")
+                .assert()
+                .failure()
+                .stdout("");
+        }
+
+        Ok(())
+    }
+
+    /// An sd that can't get at a terminal, so that `--interactive` reads its
+    /// answers from STDIN
+    fn sd_without_terminal() -> Command {
+        let mut command = std::process::Command::new(
+            assert_cmd::cargo::cargo_bin(env!("CARGO_PKG_NAME")),
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: `setsid` is safe to call between fork and exec
+            unsafe {
+                command.pre_exec(|| {
+                    libc::setsid();
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const DETACHED_PROCESS: u32 = 0x8;
+            command.creation_flags(DETACHED_PROCESS);
+        }
+        Command::from_std(command)
+    }
+
+    #[test]
+    fn interactive() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        std::fs::write(&first, "foo\nfoo foo\nfoo\n")?;
+        std::fs::write(&second, "foo\n")?;
+
+        // Unknown answers get asked again
+        sd_without_terminal()
+            .args(["--interactive", "foo", "bar"])
+            .args([&first, &second])
+            .write_stdin("y\nwhat\nn\ne\nbaz\nq\n")
+            .assert()
+            .success();
+        assert_file(&first, "bar\nfoo baz\nfoo\n");
+        assert_file(&second, "foo\n");
+
+        sd_without_terminal()
+            .args(["--interactive", "foo", "bar"])
+            .args([&first, &second])
+            .write_stdin("n\na\n")
+            .assert()
+            .success();
+        assert_file(&first, "bar\nfoo baz\nbar\n");
+        assert_file(&second, "bar\n");
+
+        // Running out of answers leaves the rest alone
+        std::fs::write(&first, "foo foo")?;
+        sd_without_terminal()
+            .args(["--interactive", "foo", "bar"])
+            .arg(&first)
+            .write_stdin("y\n")
+            .assert()
+            .success();
+        assert_file(&first, "bar foo");
+
+        sd_without_terminal()
+            .args(["--interactive", "foo", "bar"])
+            .write_stdin("foo")
+            .assert()
+            .failure();
+
+        // Rules that apply one after another can't be confirmed one by one
+        std::fs::write(&second, "a")?;
+        sd_without_terminal()
+            .args(["--interactive", "-e", "a", "b", "-e", "b", "c"])
+            .arg(&second)
+            .write_stdin("a\n")
+            .assert()
+            .failure();
+        assert_file(&second, "a");
+        sd_without_terminal()
+            .args(["--interactive", "--simultaneous", "-e", "a", "b"])
+            .args(["-e", "b", "c"])
+            .arg(&second)
+            .write_stdin("a\n")
+            .assert()
+            .success();
+        assert_file(&second, "b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_records() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "a=1\nb=2 c=\n")?;
+        let path = file.path().to_str().unwrap();
+
+        let records = |assert: assert_cmd::assert::Assert| {
+            String::from_utf8_lossy(&assert.get_output().stdout)
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<serde_json::Value>>()
+        };
+
+        let found = records(
+            sd().args(["--json", "-p", r"(?<key>\w+)=(\d+)?", "$key", path])
+                .assert()
+                .success(),
+        );
+        assert_eq!(found.len(), 5);
+        assert_eq!(
+            found[2],
+            serde_json::json!({
+                "type": "match",
+                "path": path,
+                "start": 8,
+                "end": 10,
+                "line": 2,
+                "column": 5,
+                "matched": "c=",
+                "captures": [
+                    {"name": "key", "start": 8, "end": 9, "text": "c"},
+                    null,
+                ],
+                "replacement": "c",
+            })
+        );
+        assert_eq!(
+            found[3],
+            serde_json::json!({
+                "type": "file",
+                "path": path,
+                "matches": 3,
+                "changed": true,
+            })
+        );
+        assert_eq!(
+            found[4],
+            serde_json::json!({
+                "type": "summary",
+                "files": 1,
+                "files_changed": 1,
+                "matches": 3,
+                "written": false,
+            })
+        );
+        assert_file(file.path(), "a=1\nb=2 c=\n");
+
+        let found =
+            records(sd().args(["--json", "=", ":", path]).assert().success());
+        assert_eq!(found.len(), 5);
+        assert_eq!(found[4]["written"], true);
+        assert_file(file.path(), "a:1\nb:2 c:\n");
+
+        let found = records(
+            sd().args(["--json", "x", "y"])
+                .write_stdin("x")
+                .assert()
+                .success(),
+        );
+        assert_eq!(found[0]["path"], serde_json::Value::Null);
+        assert_eq!(found[2]["written"], false);
+
+        // A file that fails doesn't keep the others from being written, and
+        // the summary only counts the ones that were
+        let dir = tempfile::tempdir()?;
+        let invalid = dir.path().join("invalid");
+        let valid = dir.path().join("valid");
+        std::fs::write(&invalid, b"\xFF\xFEf\x00\x00\xD8")?;
+        std::fs::write(&valid, "foo")?;
+        let found = records(
+            sd().args(["--json", "foo", "bar"])
+                .args([&invalid, &valid])
+                .assert()
+                .failure(),
+        );
+        assert_eq!(found.last().unwrap()["files_changed"], 1);
+        assert_eq!(found.last().unwrap()["written"], true);
+        assert_file(&valid, "bar");
+
+        std::fs::write(&valid, "foo")?;
+        let found = records(
+            sd().args(["--json", "--atomic", "foo", "bar"])
+                .args([&invalid, &valid])
+                .assert()
+                .failure(),
+        );
+        assert_eq!(found.last().unwrap()["written"], false);
+        assert_file(&valid, "foo");
+
+        // Files get written the same as without --json, or not at all
+        std::fs::write(&valid, "ab")?;
+        sd().args(["--json", "-e", "a", "b", "-e", "b", "c"])
+            .arg(&valid)
+            .assert()
+            .failure();
+        assert_file(&valid, "ab");
+        sd().args(["--json", "--simultaneous", "-e", "a", "b", "-e", "b", "c"])
+            .arg(&valid)
+            .assert()
+            .success();
+        assert_file(&valid, "bc");
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_mode() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let changes = dir.path().join("changes");
+        let stays = dir.path().join("stays");
+        std::fs::write(&changes, "foo")?;
+        std::fs::write(&stays, "bar")?;
+
+        sd().args(["--check", "foo", "bar"])
+            .args([&changes, &stays])
+            .assert()
+            .code(2)
+            .stdout(format!("{}\n", changes.display()));
+        assert_file(&changes, "foo");
+
+        // Replacing something with itself doesn't count as a change
+        sd().args(["--check", "bar", "bar"])
+            .arg(&stays)
+            .assert()
+            .success()
+            .stdout("");
+
+        sd().args(["--check", "foo", "bar"])
+            .write_stdin("foo")
+            .assert()
+            .code(2)
+            .stdout("");
+        sd().args(["--check", "foo", "bar"])
+            .write_stdin("bar")
+            .assert()
+            .success();
+
+        Ok(())
+    }
 }